@@ -0,0 +1,84 @@
+use chrono::Datelike;
+use serde_json::{Value, json};
+
+use crate::base_path::BasePath;
+use crate::post::Post;
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// The posts of one year, by month, both newest first.
+pub struct ArchiveYear<'a> {
+    pub year: i32,
+    pub months: Vec<(u32, Vec<&'a Post>)>,
+}
+
+impl ArchiveYear<'_> {
+    pub fn count(&self) -> usize {
+        self.months.iter().map(|(_, posts)| posts.len()).sum()
+    }
+
+    pub fn posts(&self) -> impl Iterator<Item = &Post> {
+        self.months
+            .iter()
+            .flat_map(|(_, posts)| posts.iter().copied())
+    }
+
+    /// The `years` entry of archive.html's data.
+    pub fn to_json(&self, base_path: &BasePath) -> Value {
+        json!({
+            "year": self.year,
+            "count": self.count(),
+            "link": base_path.url(&format!("/archive/{}/", self.year)),
+            "months": self.months.iter().map(|(month, posts)| json!({
+                "month": month,
+                "name": MONTHS[*month as usize - 1],
+                "count": posts.len(),
+                "posts": posts.iter().map(|post| json!({
+                    "title": titlecase::titlecase(&post.title),
+                    "src": post.src,
+                    "description": post.description,
+                    "date": post.date.format("%Y-%m-%d").to_string(),
+                    "tags": post.tags,
+                    "draft": post.draft,
+                })).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Groups `posts`, which are sorted newest first, by year and month.
+pub fn archive(posts: &[Post]) -> Vec<ArchiveYear<'_>> {
+    let mut years: Vec<ArchiveYear> = Vec::new();
+
+    for post in posts {
+        let (year, month) = (post.date.year(), post.date.month());
+
+        if years.last().is_none_or(|y| y.year != year) {
+            years.push(ArchiveYear {
+                year,
+                months: Vec::new(),
+            });
+        }
+
+        let months = &mut years.last_mut().unwrap().months;
+        if months.last().is_none_or(|(m, _)| *m != month) {
+            months.push((month, Vec::new()));
+        }
+        months.last_mut().unwrap().1.push(post);
+    }
+
+    years
+}
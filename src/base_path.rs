@@ -0,0 +1,128 @@
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, HelperResult, JsonValue, Output, RenderContext,
+};
+
+/// Where the blog lives under its domain, e.g. `/blog` for a GitHub project
+/// page at `user.github.io/blog/`. Every root-relative URL in the generated
+/// pages goes through it.
+#[derive(Debug, Clone, Default)]
+pub struct BasePath {
+    /// Empty at the root, otherwise starts with a slash and doesn't end with one.
+    prefix: String,
+}
+
+impl BasePath {
+    /// Accepts `blog`, `/blog` and `/blog/` alike.
+    pub fn new(prefix: &str) -> Self {
+        let prefix = prefix.trim().trim_matches('/');
+        BasePath {
+            prefix: if prefix.is_empty() {
+                String::new()
+            } else {
+                format!("/{}", prefix)
+            },
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// `/tags/rust/` becomes `/blog/tags/rust/`. Paths without a leading slash
+    /// are treated as root-relative too.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.prefix, path.trim_start_matches('/'))
+    }
+
+    /// The blog's URL including the prefix, without a trailing slash, so
+    /// `{blog_url}/{path}` links work. `url` in quipquick.toml may be written
+    /// with or without the prefix.
+    pub fn blog_url(&self, url: &str) -> String {
+        let url = url.trim_end_matches('/');
+        if self.prefix.is_empty() || url.ends_with(&self.prefix) {
+            url.to_string()
+        } else {
+            format!("{}{}", url, self.prefix)
+        }
+    }
+}
+
+/// `{{url "/style.css"}}` or `{{url "/tags/" slug}}` in templates. The
+/// parameters are joined before the prefix is added.
+impl HelperDef for BasePath {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        r: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let path: String = h
+            .params()
+            .iter()
+            .map(|p| match p.value() {
+                JsonValue::String(s) => s.clone(),
+                JsonValue::Null => String::new(),
+                value => value.to_string(),
+            })
+            .collect();
+
+        out.write(&r.get_escape_fn()(&self.url(&path)))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_is_normalized() {
+        for prefix in ["blog", "/blog", "/blog/", " blog/ "] {
+            assert_eq!(BasePath::new(prefix).prefix(), "/blog");
+        }
+        assert_eq!(BasePath::new("").prefix(), "");
+        assert_eq!(BasePath::new("/").prefix(), "");
+    }
+
+    #[test]
+    fn url_adds_prefix() {
+        let base_path = BasePath::new("blog");
+        assert_eq!(base_path.url("/tags/rust/"), "/blog/tags/rust/");
+        assert_eq!(base_path.url("style.css"), "/blog/style.css");
+        assert_eq!(base_path.url("/"), "/blog/");
+        assert_eq!(BasePath::default().url("/tags/rust/"), "/tags/rust/");
+    }
+
+    #[test]
+    fn blog_url_with_or_without_prefix() {
+        let base_path = BasePath::new("blog");
+        assert_eq!(
+            base_path.blog_url("https://user.github.io"),
+            "https://user.github.io/blog"
+        );
+        assert_eq!(
+            base_path.blog_url("https://user.github.io/blog/"),
+            "https://user.github.io/blog"
+        );
+        assert_eq!(
+            BasePath::default().blog_url("https://example.com/"),
+            "https://example.com"
+        );
+    }
+
+    #[test]
+    fn url_helper_joins_parameters() {
+        let mut reg = Handlebars::new();
+        reg.register_helper("url", Box::new(BasePath::new("blog")));
+
+        let html = reg
+            .render_template(
+                r#"{{url "/tags/" slug "/"}} {{url "/style.css"}}"#,
+                &serde_json::json!({"slug": "c++"}),
+            )
+            .unwrap();
+        assert_eq!(html, "/blog/tags/c++/ /blog/style.css");
+    }
+}
@@ -0,0 +1,136 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const CACHE_FOLDER: &str = ".quipquick-cache";
+
+/// Generated files kept between publishes, keyed by the hash of their source and
+/// the parameters used to generate them.
+pub struct BuildCache {
+    folder: PathBuf,
+    /// `false` with `pub --no-cache`: everything is regenerated, and the cache
+    /// is refreshed with the results.
+    read: bool,
+    used: Mutex<HashSet<String>>,
+}
+
+impl BuildCache {
+    pub fn new(folder: &str, read: bool) -> Self {
+        BuildCache {
+            folder: PathBuf::from(folder),
+            read,
+            used: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn hash(bytes: &[u8]) -> String {
+        Sha256::digest(bytes)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// The cached file for `key`, if there is one.
+    pub fn get(&self, key: &str) -> Option<PathBuf> {
+        self.used.lock().unwrap().insert(key.to_string());
+
+        let path = self.folder.join(key);
+        if self.read && path.exists() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    fn create_folder(&self) {
+        fs::create_dir_all(&self.folder).unwrap_or_else(|_| {
+            panic!("Unable to create cache folder: {}.", self.folder.display())
+        });
+    }
+
+    /// Stores a copy of `file` under `key`.
+    pub fn put(&self, key: &str, file: &Path) {
+        self.used.lock().unwrap().insert(key.to_string());
+        self.create_folder();
+
+        if let Err(e) = fs::copy(file, self.folder.join(key)) {
+            println!("Unable to cache {}: {}", file.display(), e);
+        }
+    }
+
+    /// Whether entries are read, which `pub --no-cache` turns off.
+    pub fn reads(&self) -> bool {
+        self.read
+    }
+
+    /// The entry for `key`, read even with `pub --no-cache`. For entries that
+    /// describe the target rather than cache generated output.
+    pub fn read_string(&self, key: &str) -> Option<String> {
+        self.used.lock().unwrap().insert(key.to_string());
+        fs::read_to_string(self.folder.join(key)).ok()
+    }
+
+    pub fn get_string(&self, key: &str) -> Option<String> {
+        self.get(key).and_then(|path| fs::read_to_string(path).ok())
+    }
+
+    pub fn put_string(&self, key: &str, value: &str) {
+        self.used.lock().unwrap().insert(key.to_string());
+        self.create_folder();
+
+        if let Err(e) = fs::write(self.folder.join(key), value) {
+            println!("Unable to write cache entry {}: {}", key, e);
+        }
+    }
+
+    /// Marks entries used by an earlier publish as still in use, e.g. the images
+    /// of a post that didn't have to be rendered again.
+    pub fn keep(&self, keys: &[String]) {
+        self.used.lock().unwrap().extend(keys.iter().cloned());
+    }
+
+    /// Deletes entries that weren't used by this publish, e.g. for images that
+    /// were edited or removed. Anything that can't be removed is reported and
+    /// left for the next publish; folders aren't entries and are left alone.
+    pub fn prune(&self) {
+        let used = self.used.lock().unwrap();
+        let Ok(entries) = fs::read_dir(&self.folder) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            if !entry.file_type().is_ok_and(|t| t.is_file()) {
+                continue;
+            }
+
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !used.contains(&name)
+                && let Err(e) = fs::remove_file(entry.path())
+            {
+                println!("Unable to remove cache entry {}: {}", name, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_removes_unused_files_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BuildCache::new(&dir.path().to_string_lossy(), true);
+        cache.put_string("used", "1");
+        fs::write(dir.path().join("unused"), "2").unwrap();
+        fs::create_dir(dir.path().join("folder")).unwrap();
+
+        cache.prune();
+
+        assert!(dir.path().join("used").exists());
+        assert!(!dir.path().join("unused").exists());
+        assert!(dir.path().join("folder").exists());
+    }
+}
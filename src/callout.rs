@@ -0,0 +1,133 @@
+use markdown::mdast::Node::{self, Break, Paragraph, Text};
+use markdown::mdast;
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// `[!NOTE]` at the start of a blockquote.
+static MARKER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\[!([\w-]+)\]").unwrap());
+
+/// A kind of callout, e.g. `[!NOTE]`.
+#[derive(Debug, Clone)]
+pub struct CalloutType {
+    /// Shown when the marker isn't followed by a title.
+    pub title: String,
+    /// Rendered as a `<details>` element that starts closed.
+    pub collapsible: bool,
+}
+
+/// The GitHub callout types, plus `[!DETAILS]` for collapsible sections.
+pub fn builtin_callout_types() -> HashMap<String, CalloutType> {
+    let mut types = HashMap::new();
+
+    for (name, title) in [
+        ("note", "Note"),
+        ("tip", "Tip"),
+        ("important", "Important"),
+        ("warning", "Warning"),
+        ("caution", "Caution"),
+    ] {
+        types.insert(
+            name.to_string(),
+            CalloutType {
+                title: title.to_string(),
+                collapsible: false,
+            },
+        );
+    }
+
+    types.insert(
+        "details".to_string(),
+        CalloutType {
+            title: "Details".to_string(),
+            collapsible: true,
+        },
+    );
+
+    types
+}
+
+/// A blockquote that starts with a known `[!TYPE]` marker.
+pub struct Callout {
+    /// Lowercase type name, used for the `callout-{kind}` class.
+    pub kind: String,
+    pub callout_type: CalloutType,
+    /// Inline content after the marker on the first line. Empty when the default
+    /// title should be used.
+    pub title: Vec<Node>,
+    pub children: Vec<Node>,
+}
+
+/// Recognises a callout from the blockquote's children. The first line of the
+/// first paragraph holds the marker and an optional title; the rest of the
+/// paragraph and the following blocks are the body.
+pub fn parse_callout(
+    children: &[Node],
+    types: &HashMap<String, CalloutType>,
+) -> Option<Callout> {
+    let Some(Paragraph(p)) = children.first() else {
+        return None;
+    };
+    let Some(Text(first)) = p.children.first() else {
+        return None;
+    };
+    let marker = MARKER.captures(&first.value)?;
+    let kind = marker.get(1).unwrap().as_str().to_lowercase();
+    let callout_type = types.get(&kind)?.clone();
+
+    let mut inlines = p.children.clone();
+    if let Some(Text(first)) = inlines.first_mut() {
+        first.value = first.value[marker.get(0).unwrap().end()..]
+            .trim_start_matches([' ', '\t'])
+            .to_string();
+    }
+
+    let mut title = Vec::new();
+    let mut body = Vec::new();
+    let mut in_title = true;
+
+    for n in inlines {
+        if !in_title {
+            body.push(n);
+            continue;
+        }
+
+        match n {
+            Text(t) if t.value.contains('\n') => {
+                let (line, rest) = t.value.split_once('\n').unwrap();
+                if !line.trim().is_empty() {
+                    title.push(Text(mdast::Text {
+                        value: line.trim_end().to_string(),
+                        position: None,
+                    }));
+                }
+                if !rest.is_empty() {
+                    body.push(Text(mdast::Text {
+                        value: rest.to_string(),
+                        position: None,
+                    }));
+                }
+                in_title = false;
+            }
+            Break(_) => in_title = false,
+            Text(t) if t.value.is_empty() => {}
+            n => title.push(n),
+        }
+    }
+
+    let mut callout_children = Vec::new();
+    if !body.is_empty() {
+        callout_children.push(Paragraph(mdast::Paragraph {
+            children: body,
+            position: None,
+        }));
+    }
+    callout_children.extend(children[1..].iter().cloned());
+
+    Some(Callout {
+        kind,
+        callout_type,
+        title,
+        children: callout_children,
+    })
+}
@@ -0,0 +1,199 @@
+use serde::Serialize;
+use serde_json::{Value, json};
+use slugify::slugify;
+use std::collections::BTreeMap;
+
+use crate::base_path::BasePath;
+use crate::post::Post;
+
+/// A link to a category's landing page.
+#[derive(Debug, Clone, Serialize)]
+pub struct Breadcrumb {
+    pub name: String,
+    pub link: String,
+}
+
+/// A category from a post's `categories`, e.g. `Graphics/WebGPU`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Category {
+    /// The slugs of the category and its parents, e.g. `graphics/webgpu`.
+    pub path: String,
+    pub name: String,
+    pub link: String,
+    /// The top category down to this one.
+    pub breadcrumbs: Vec<Breadcrumb>,
+}
+
+impl Category {
+    /// Empty segments are ignored, so `graphics//webgpu/` is
+    /// `graphics/webgpu`. An error when nothing is left, or when a segment
+    /// has no letters or digits for its URL.
+    pub fn parse(category: &str, base_path: &BasePath) -> Result<Category, String> {
+        let mut path = String::new();
+        let mut breadcrumbs = Vec::new();
+
+        for segment in category
+            .split('/')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+        {
+            let slug = slugify!(segment);
+            if slug.is_empty() {
+                return Err(format!(
+                    "Category {} has a part, {}, without letters or digits for its URL.",
+                    category, segment
+                ));
+            }
+
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(&slug);
+
+            breadcrumbs.push(Breadcrumb {
+                name: segment.to_string(),
+                link: base_path.url(&format!("/categories/{}/", path)),
+            });
+        }
+
+        let Some(last) = breadcrumbs.last().cloned() else {
+            return Err(String::from("Category is empty."));
+        };
+        Ok(Category {
+            path,
+            name: last.name,
+            link: last.link,
+            breadcrumbs,
+        })
+    }
+
+    /// The paths of the category and its parents, top category first.
+    fn ancestors(&self) -> impl Iterator<Item = &str> {
+        self.path
+            .match_indices('/')
+            .map(|(i, _)| &self.path[..i])
+            .chain(std::iter::once(self.path.as_str()))
+    }
+}
+
+/// A category's landing page, listing the posts of the category and of its
+/// subcategories.
+pub struct CategoryPage {
+    pub path: String,
+    pub name: String,
+    pub breadcrumbs: Vec<Breadcrumb>,
+    /// Indices into the post list, newest first.
+    pub posts: Vec<usize>,
+    /// Paths of the direct subcategories.
+    pub subcategories: Vec<String>,
+}
+
+/// Every category and parent category of `posts`, which are sorted newest
+/// first, by path. A category is named after its newest post.
+pub fn category_pages(posts: &[Post]) -> BTreeMap<String, CategoryPage> {
+    let mut pages: BTreeMap<String, CategoryPage> = BTreeMap::new();
+
+    for (index, post) in posts.iter().enumerate() {
+        for category in &post.categories {
+            for (depth, path) in category.ancestors().enumerate() {
+                let page = pages
+                    .entry(path.to_string())
+                    .or_insert_with(|| CategoryPage {
+                        path: path.to_string(),
+                        name: category.breadcrumbs[depth].name.clone(),
+                        breadcrumbs: category.breadcrumbs[..=depth].to_vec(),
+                        posts: Vec::new(),
+                        subcategories: Vec::new(),
+                    });
+
+                // A post in two subcategories is listed once in their parent.
+                if page.posts.last() != Some(&index) {
+                    page.posts.push(index);
+                }
+            }
+        }
+    }
+
+    let paths: Vec<String> = pages.keys().cloned().collect();
+    for path in paths {
+        if let Some((parent, _)) = path.rsplit_once('/') {
+            pages.get_mut(parent).unwrap().subcategories.push(path);
+        }
+    }
+
+    pages
+}
+
+/// Names `category` and its breadcrumbs after their landing pages, so a
+/// category is shown the same way wherever it's written differently.
+pub fn use_page_names(category: &mut Category, pages: &BTreeMap<String, CategoryPage>) {
+    let paths: Vec<String> = category.ancestors().map(|path| path.to_string()).collect();
+
+    for (breadcrumb, path) in category.breadcrumbs.iter_mut().zip(paths) {
+        if let Some(page) = pages.get(&path) {
+            breadcrumb.name = page.name.clone();
+        }
+    }
+
+    category.name = category.breadcrumbs.last().unwrap().name.clone();
+}
+
+/// The categories index page's `categories`: the top categories, each with its
+/// post count and, recursively, its subcategories.
+pub fn category_tree(pages: &BTreeMap<String, CategoryPage>) -> Vec<Value> {
+    fn node(page: &CategoryPage, pages: &BTreeMap<String, CategoryPage>) -> Value {
+        json!({
+            "name": page.name,
+            "link": page.breadcrumbs.last().unwrap().link,
+            "count": page.posts.len(),
+            "subcategories": page
+                .subcategories
+                .iter()
+                .map(|path| node(&pages[path], pages))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    pages
+        .values()
+        .filter(|page| !page.path.contains('/'))
+        .map(|page| node(page, pages))
+        .collect()
+}
+
+impl CategoryPage {
+    /// The `subcategories` of a category page's data, with their post counts.
+    pub fn subcategories_json(&self, pages: &BTreeMap<String, CategoryPage>) -> Vec<Value> {
+        self.subcategories
+            .iter()
+            .map(|path| {
+                let page = &pages[path];
+                json!({
+                    "name": page.name,
+                    "link": page.breadcrumbs.last().unwrap().link,
+                    "count": page.posts.len(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_segments_are_ignored() {
+        let category = Category::parse("Graphics//WebGPU/", &BasePath::new("blog")).unwrap();
+
+        assert_eq!(category.path, "graphics/webgpu");
+        assert_eq!(category.link, "/blog/categories/graphics/webgpu/");
+        assert_eq!(category.breadcrumbs[0].link, "/blog/categories/graphics/");
+    }
+
+    #[test]
+    fn segments_without_a_slug_are_rejected() {
+        assert!(Category::parse("Graphics/!!!", &BasePath::default()).is_err());
+        assert!(Category::parse(" / ", &BasePath::default()).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+use markdown::mdast::Node::{self, Image, ImageReference, Math, Paragraph, Table, Text};
+use regex::Regex;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// `{#fig:foo}` in an image's alt text or at the end of a table caption.
+static LABEL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\s*\{#((?:eq|fig|tbl):[\w-]+)\}").unwrap());
+
+/// `\label{eq:foo}` inside display math.
+static EQUATION_LABEL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\\label\{([^}]+)\}").unwrap());
+
+/// `\ref{label}`, `\eqref{label}` or `@eq:foo`/`@fig:foo`/`@tbl:foo` in text.
+static REFERENCE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\\(eq)?ref\{([^}]+)\}|\B@((?:eq|fig|tbl):[\w-]*\w)").unwrap()
+});
+
+const TABLE_CAPTION_PREFIX: &str = "Table:";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LabelKind {
+    Equation,
+    Figure,
+    Table,
+}
+
+/// A numbered equation, figure or table that can be referenced by its label.
+#[derive(Debug, Clone)]
+pub struct CrossRef {
+    pub kind: LabelKind,
+    pub number: usize,
+}
+
+impl CrossRef {
+    /// How an `@label` reference reads in the text.
+    fn display(&self) -> String {
+        match self.kind {
+            LabelKind::Equation => format!("Equation ({})", self.number),
+            LabelKind::Figure => format!("Figure {}", self.number),
+            LabelKind::Table => format!("Table {}", self.number),
+        }
+    }
+
+    /// Prepended to figure and table captions.
+    pub fn caption_prefix(&self) -> String {
+        format!("{}: ", self.display())
+    }
+}
+
+/// Removes a `{#kind:id}` label from `text`, returning the remaining text and the label.
+pub fn split_label(text: &str) -> (String, Option<String>) {
+    match LABEL.captures(text) {
+        Some(c) => (
+            LABEL.replace(text, "").to_string(),
+            Some(c.get(1).unwrap().as_str().to_string()),
+        ),
+        None => (text.to_string(), None),
+    }
+}
+
+/// Removes `\label{...}` from display math, which neither KaTeX nor the MathML
+/// renderer understand.
+pub fn split_equation_label(tex: &str) -> (String, Option<String>) {
+    match EQUATION_LABEL.captures(tex) {
+        Some(c) => (
+            EQUATION_LABEL.replace_all(tex, "").to_string(),
+            Some(c.get(1).unwrap().as_str().to_string()),
+        ),
+        None => (tex.to_string(), None),
+    }
+}
+
+/// A paragraph right after a table that starts with `Table:` is that table's
+/// caption. Returns the caption content, without the prefix and label, and the label.
+pub fn table_caption(node: &Node) -> Option<(Vec<Node>, Option<String>)> {
+    let Paragraph(p) = node else {
+        return None;
+    };
+    let Some(Text(first)) = p.children.first() else {
+        return None;
+    };
+    if !first.value.starts_with(TABLE_CAPTION_PREFIX) {
+        return None;
+    }
+
+    let mut children = p.children.clone();
+    if let Some(Text(first)) = children.first_mut() {
+        first.value = first.value[TABLE_CAPTION_PREFIX.len()..]
+            .trim_start()
+            .to_string();
+    }
+
+    let mut label = None;
+    if let Some(Text(last)) = children.last_mut() {
+        let (text, l) = split_label(&last.value);
+        last.value = text;
+        label = l;
+    }
+
+    Some((children, label))
+}
+
+#[derive(Default)]
+struct Counters {
+    equations: usize,
+    figures: usize,
+    tables: usize,
+}
+
+fn insert_label(
+    labels: &mut HashMap<String, CrossRef>,
+    label: String,
+    cross_ref: CrossRef,
+    folder: &str,
+    log: &mut Vec<String>,
+) {
+    match labels.entry(label) {
+        Entry::Occupied(e) => log.push(format!(
+            "Warning: {}/content.md defines label {} more than once.",
+            folder,
+            e.key()
+        )),
+        Entry::Vacant(e) => {
+            e.insert(cross_ref);
+        }
+    }
+}
+
+fn collect(
+    children: &[Node],
+    labels: &mut HashMap<String, CrossRef>,
+    counters: &mut Counters,
+    folder: &str,
+    log: &mut Vec<String>,
+) {
+    for (index, n) in children.iter().enumerate() {
+        match n {
+            // Every display equation is numbered, matching the CSS counter in the
+            // theme; labels only make the number referenceable.
+            Math(m) => {
+                counters.equations += 1;
+                if let (_, Some(label)) = split_equation_label(&m.value) {
+                    let cross_ref = CrossRef {
+                        kind: LabelKind::Equation,
+                        number: counters.equations,
+                    };
+                    insert_label(labels, label, cross_ref, folder, log);
+                }
+            }
+            Image(i) => {
+                if let (_, Some(label)) = split_label(&i.alt) {
+                    counters.figures += 1;
+                    let cross_ref = CrossRef {
+                        kind: LabelKind::Figure,
+                        number: counters.figures,
+                    };
+                    insert_label(labels, label, cross_ref, folder, log);
+                }
+            }
+            ImageReference(i) => {
+                if let (_, Some(label)) = split_label(&i.alt) {
+                    counters.figures += 1;
+                    let cross_ref = CrossRef {
+                        kind: LabelKind::Figure,
+                        number: counters.figures,
+                    };
+                    insert_label(labels, label, cross_ref, folder, log);
+                }
+            }
+            Table(_) => {
+                if let Some((_, Some(label))) = children.get(index + 1).and_then(table_caption) {
+                    counters.tables += 1;
+                    let cross_ref = CrossRef {
+                        kind: LabelKind::Table,
+                        number: counters.tables,
+                    };
+                    insert_label(labels, label, cross_ref, folder, log);
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(c) = n.children() {
+            collect(c, labels, counters, folder, log);
+        }
+    }
+}
+
+/// Numbers equations, labelled figures and labelled tables in document order, so
+/// references can be resolved even when they come before the label.
+pub fn collect_labels(
+    children: &[Node],
+    folder: &str,
+    log: &mut Vec<String>,
+) -> HashMap<String, CrossRef> {
+    let mut labels = HashMap::new();
+    collect(children, &mut labels, &mut Counters::default(), folder, log);
+    labels
+}
+
+/// Writes escaped `text` to `output`, turning references into links to their
+/// targets. Unknown labels are reported and rendered as `??`.
+pub fn push_text_with_refs(
+    text: &str,
+    labels: &HashMap<String, CrossRef>,
+    folder: &str,
+    output: &mut String,
+    log: &mut Vec<String>,
+) {
+    let mut last = 0;
+
+    for c in REFERENCE.captures_iter(text) {
+        let whole = c.get(0).unwrap();
+        output.push_str(html_escape::encode_text(&text[last..whole.start()]).to_string().as_str());
+        last = whole.end();
+
+        let label = c.get(2).or(c.get(3)).unwrap().as_str();
+
+        match labels.get(label) {
+            Some(cross_ref) => {
+                let shown = if c.get(3).is_some() {
+                    cross_ref.display()
+                } else if c.get(1).is_some() {
+                    format!("({})", cross_ref.number)
+                } else {
+                    cross_ref.number.to_string()
+                };
+                output.push_str(
+                    format!(
+                        "<a class=\"xref\" href=\"#{}\">{}</a>",
+                        html_escape::encode_double_quoted_attribute(label),
+                        shown
+                    )
+                    .as_str(),
+                );
+            }
+            None => {
+                log.push(format!(
+                    "Warning: {}/content.md references unknown label {}.",
+                    folder, label
+                ));
+                output.push_str("<span class=\"xref-missing\">??</span>");
+            }
+        }
+    }
+
+    output.push_str(html_escape::encode_text(&text[last..]).to_string().as_str());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels() -> HashMap<String, CrossRef> {
+        HashMap::from([
+            (
+                "eq:euler".to_string(),
+                CrossRef {
+                    kind: LabelKind::Equation,
+                    number: 2,
+                },
+            ),
+            (
+                "fig:cat".to_string(),
+                CrossRef {
+                    kind: LabelKind::Figure,
+                    number: 1,
+                },
+            ),
+        ])
+    }
+
+    fn refs(text: &str) -> (String, Vec<String>) {
+        let mut output = String::new();
+        let mut log = Vec::new();
+        push_text_with_refs(text, &labels(), "post", &mut output, &mut log);
+        (output, log)
+    }
+
+    #[test]
+    fn split_label_removes_label() {
+        assert_eq!(
+            split_label("A cat {#fig:cat}"),
+            ("A cat".to_string(), Some("fig:cat".to_string()))
+        );
+        assert_eq!(
+            split_label("Results{#tbl:results-2}"),
+            ("Results".to_string(), Some("tbl:results-2".to_string()))
+        );
+    }
+
+    #[test]
+    fn split_label_ignores_unknown_kinds() {
+        assert_eq!(
+            split_label("A cat {#cat}"),
+            ("A cat {#cat}".to_string(), None)
+        );
+        assert_eq!(
+            split_label("A cat {#sec:cat}"),
+            ("A cat {#sec:cat}".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn split_equation_label_removes_label() {
+        assert_eq!(
+            split_equation_label(r"e^{i\pi} + 1 = 0 \label{eq:euler}"),
+            (
+                r"e^{i\pi} + 1 = 0 ".to_string(),
+                Some("eq:euler".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn reference_forms() {
+        assert_eq!(
+            refs("See @fig:cat.").0,
+            "See <a class=\"xref\" href=\"#fig:cat\">Figure 1</a>."
+        );
+        assert_eq!(
+            refs(r"see \eqref{eq:euler}").0,
+            "see <a class=\"xref\" href=\"#eq:euler\">(2)</a>"
+        );
+        assert_eq!(
+            refs(r"figure \ref{fig:cat}").0,
+            "figure <a class=\"xref\" href=\"#fig:cat\">1</a>"
+        );
+    }
+
+    #[test]
+    fn reference_skips_email_addresses() {
+        assert_eq!(refs("me@fig:cat").0, "me@fig:cat");
+    }
+
+    #[test]
+    fn reference_escapes_text() {
+        assert_eq!(
+            refs("a < b, @fig:cat").0,
+            "a &lt; b, <a class=\"xref\" href=\"#fig:cat\">Figure 1</a>"
+        );
+    }
+
+    #[test]
+    fn unknown_reference_is_reported() {
+        let (output, log) = refs("@tbl:missing");
+        assert_eq!(output, "<span class=\"xref-missing\">??</span>");
+        assert_eq!(
+            log,
+            vec!["Warning: post/content.md references unknown label tbl:missing."]
+        );
+    }
+}
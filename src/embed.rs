@@ -0,0 +1,367 @@
+use handlebars::Handlebars;
+use regex::Regex;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use crate::new::Template;
+
+/// `key: value` or `key = value`. A bare URL such as `https://...` is not an option.
+static OPTION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^([A-Za-z_][\w-]*)\s*(?::(?:\s+|$)|=)\s*(.*)$").unwrap());
+
+static YOUTUBE_URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?:v=|youtu\.be/|embed/|shorts/)([\w-]{11})").unwrap());
+
+static CODEPEN_URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"codepen\.io/([\w-]+)/(?:pen|embed)/([\w-]+)").unwrap());
+
+/// The options written in an embed fence, e.g.
+///
+/// ```youtube
+/// id: dQw4w9WgXcQ
+/// start: 1:30
+/// ```
+///
+/// The first line that isn't an option sets the provider's main option, so
+/// ` ```youtube ` fences that only contain a video id keep working.
+pub struct EmbedOptions<'a> {
+    values: HashMap<String, String>,
+    /// The post folder, for local media.
+    pub folder: &'a str,
+    pub target_folder: &'a str,
+}
+
+impl EmbedOptions<'_> {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| v.as_str())
+    }
+
+    pub fn require(&self, key: &str) -> Result<&str, String> {
+        self.get(key).ok_or_else(|| format!("missing option {}", key))
+    }
+
+    pub fn flag(&self, key: &str, default: bool) -> bool {
+        match self.get(key) {
+            Some(v) => matches!(v, "true" | "yes" | "1" | ""),
+            None => default,
+        }
+    }
+
+    /// `start` in seconds, written as `90`, `1:30` or `1m30s`.
+    pub fn start(&self) -> Result<Option<u32>, String> {
+        let Some(start) = self.get("start") else {
+            return Ok(None);
+        };
+
+        let mut seconds = 0;
+        if start.contains(':') {
+            for part in start.split(':') {
+                seconds = seconds * 60 + part.trim().parse::<u32>().map_err(|_| bad_start(start))?;
+            }
+        } else if start.ends_with('s') || start.contains(['h', 'm']) {
+            let mut number = String::new();
+            for c in start.chars() {
+                match c {
+                    '0'..='9' => number.push(c),
+                    'h' | 'm' | 's' => {
+                        let n = number.parse::<u32>().map_err(|_| bad_start(start))?;
+                        seconds += n * match c {
+                            'h' => 3600,
+                            'm' => 60,
+                            _ => 1,
+                        };
+                        number.clear();
+                    }
+                    _ => return Err(bad_start(start)),
+                }
+            }
+        } else {
+            seconds = start.parse::<u32>().map_err(|_| bad_start(start))?;
+        }
+
+        Ok(Some(seconds))
+    }
+
+    /// Copies a media file next to the post, unless it's a remote URL.
+    pub fn copy_local(&self, src: &str) -> Result<(), String> {
+        if src.contains("://") {
+            return Ok(());
+        }
+
+        let copy_from = format!("{}/{}", self.folder, src);
+        let copy_to = format!("{}/{}/{}", self.target_folder, self.folder, src);
+        fs::copy(&copy_from, &copy_to)
+            .map(|_| ())
+            .map_err(|e| format!("unable to copy {}: {}", copy_from, e))
+    }
+}
+
+fn bad_start(start: &str) -> String {
+    format!("invalid start time {}", start)
+}
+
+/// A kind of embed, selected by the fence language.
+pub trait EmbedProvider {
+    /// The option set by a line that isn't `key: value`.
+    fn main_option(&self) -> &'static str {
+        "id"
+    }
+
+    /// Turns the fence options into the data for the provider's template.
+    /// `data` already holds the options and `padding_top`, derived from `aspect`.
+    fn data(&self, options: &EmbedOptions, data: &mut Map<String, Value>) -> Result<(), String>;
+}
+
+struct YouTube;
+
+impl EmbedProvider for YouTube {
+    fn data(&self, options: &EmbedOptions, data: &mut Map<String, Value>) -> Result<(), String> {
+        let id = options.require("id")?;
+        let id = match YOUTUBE_URL.captures(id) {
+            Some(c) => c.get(1).unwrap().as_str(),
+            None => id,
+        };
+
+        let mut src = format!("https://www.youtube.com/embed/{}", id);
+        if let Some(start) = options.start()? {
+            src.push_str(format!("?start={}", start).as_str());
+        }
+        data.insert("src".to_string(), json!(src));
+        Ok(())
+    }
+}
+
+struct Vimeo;
+
+impl EmbedProvider for Vimeo {
+    fn data(&self, options: &EmbedOptions, data: &mut Map<String, Value>) -> Result<(), String> {
+        let id = options.require("id")?;
+        let id = id.trim_end_matches('/').rsplit('/').next().unwrap();
+
+        let mut src = format!("https://player.vimeo.com/video/{}", id);
+        if let Some(start) = options.start()? {
+            src.push_str(format!("#t={}s", start).as_str());
+        }
+        data.insert("src".to_string(), json!(src));
+        Ok(())
+    }
+}
+
+struct Gist;
+
+impl EmbedProvider for Gist {
+    fn data(&self, options: &EmbedOptions, data: &mut Map<String, Value>) -> Result<(), String> {
+        let id = options.require("id")?;
+        let id = id
+            .trim_start_matches("https://gist.github.com/")
+            .trim_end_matches(".js");
+
+        let mut src = format!("https://gist.github.com/{}.js", id);
+        if let Some(file) = options.get("file") {
+            src.push_str(format!("?file={}", file).as_str());
+        }
+        data.insert("src".to_string(), json!(src));
+        Ok(())
+    }
+}
+
+struct CodePen;
+
+impl EmbedProvider for CodePen {
+    fn data(&self, options: &EmbedOptions, data: &mut Map<String, Value>) -> Result<(), String> {
+        let id = options.require("id")?;
+        let (user, pen) = match CODEPEN_URL.captures(id) {
+            Some(c) => (c.get(1).unwrap().as_str(), c.get(2).unwrap().as_str()),
+            None => id
+                .split_once('/')
+                .ok_or_else(|| format!("expected user/pen or a pen URL, got {}", id))?,
+        };
+
+        data.insert(
+            "src".to_string(),
+            json!(format!(
+                "https://codepen.io/{}/embed/{}?default-tab={}",
+                user,
+                pen,
+                options.get("tab").unwrap_or("result")
+            )),
+        );
+        data.insert(
+            "height".to_string(),
+            json!(options.get("height").unwrap_or("400")),
+        );
+        Ok(())
+    }
+}
+
+struct Video;
+
+impl EmbedProvider for Video {
+    fn main_option(&self) -> &'static str {
+        "src"
+    }
+
+    fn data(&self, options: &EmbedOptions, data: &mut Map<String, Value>) -> Result<(), String> {
+        options.copy_local(options.require("src")?)?;
+        if let Some(poster) = options.get("poster") {
+            options.copy_local(poster)?;
+        }
+
+        data.insert("controls".to_string(), json!(options.flag("controls", true)));
+        data.insert("autoplay".to_string(), json!(options.flag("autoplay", false)));
+        data.insert("loop".to_string(), json!(options.flag("loop", false)));
+        // Browsers only autoplay muted videos.
+        data.insert(
+            "muted".to_string(),
+            json!(options.flag("muted", options.flag("autoplay", false))),
+        );
+        Ok(())
+    }
+}
+
+struct Audio;
+
+impl EmbedProvider for Audio {
+    fn main_option(&self) -> &'static str {
+        "src"
+    }
+
+    fn data(&self, options: &EmbedOptions, data: &mut Map<String, Value>) -> Result<(), String> {
+        options.copy_local(options.require("src")?)?;
+
+        data.insert("controls".to_string(), json!(options.flag("controls", true)));
+        data.insert("autoplay".to_string(), json!(options.flag("autoplay", false)));
+        data.insert("loop".to_string(), json!(options.flag("loop", false)));
+        Ok(())
+    }
+}
+
+struct IFrame;
+
+impl EmbedProvider for IFrame {
+    fn main_option(&self) -> &'static str {
+        "src"
+    }
+
+    fn data(&self, options: &EmbedOptions, _data: &mut Map<String, Value>) -> Result<(), String> {
+        options.require("src")?;
+        Ok(())
+    }
+}
+
+/// Embed providers keyed by fence language, each rendered through
+/// `template/embeds/{name}.html` when the theme has one, or the built-in template.
+pub struct EmbedRegistry {
+    providers: HashMap<String, Box<dyn EmbedProvider + Send + Sync>>,
+    templates: Handlebars<'static>,
+    theme_folder: String,
+}
+
+impl EmbedRegistry {
+    pub fn new(theme_folder: &str) -> Self {
+        let mut templates = Handlebars::new();
+        // Values mostly end up in attributes, where `=` in URLs needn't be escaped.
+        templates.register_escape_fn(|s| html_escape::encode_quoted_attribute(s).to_string());
+
+        let mut registry = EmbedRegistry {
+            providers: HashMap::new(),
+            templates,
+            theme_folder: theme_folder.to_string(),
+        };
+
+        registry.register("youtube", Box::new(YouTube));
+        registry.register("vimeo", Box::new(Vimeo));
+        registry.register("gist", Box::new(Gist));
+        registry.register("codepen", Box::new(CodePen));
+        registry.register("video", Box::new(Video));
+        registry.register("audio", Box::new(Audio));
+        registry.register("iframe", Box::new(IFrame));
+
+        registry
+    }
+
+    /// Adds a provider for ` ```{name} ` fences. Its template is
+    /// `embeds/{name}.html` in the theme, falling back to the one in `template_src`.
+    pub fn register(&mut self, name: &str, provider: Box<dyn EmbedProvider + Send + Sync>) {
+        let theme_template = format!("{}/embeds/{}.html", self.theme_folder, name);
+        let template = if Path::new(&theme_template).exists() {
+            fs::read_to_string(&theme_template).unwrap()
+        } else {
+            let file = Template::get(format!("embeds/{}.html", name).as_str())
+                .unwrap_or_else(|| panic!("No template for the {} embed.", name));
+            String::from_utf8(file.data.to_vec()).unwrap()
+        };
+
+        self.templates
+            .register_template_string(name, template)
+            .unwrap_or_else(|e| panic!("Invalid template for the {} embed: {}", name, e));
+        self.providers.insert(name.to_string(), provider);
+    }
+
+    /// Renders an embed fence. Returns `None` when no provider handles `lang`.
+    pub fn render(
+        &self,
+        lang: &str,
+        body: &str,
+        folder: &str,
+        target_folder: &str,
+    ) -> Option<Result<String, String>> {
+        let provider = self.providers.get(lang)?;
+
+        let mut values = HashMap::new();
+        for line in body.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+            match OPTION.captures(line) {
+                Some(c) => values.insert(
+                    c.get(1).unwrap().as_str().to_lowercase(),
+                    c.get(2).unwrap().as_str().trim().to_string(),
+                ),
+                None if !values.contains_key(provider.main_option()) => {
+                    values.insert(provider.main_option().to_string(), line.to_string())
+                }
+                // A bare word after the main option is a flag, e.g. `loop`.
+                None => values.insert(line.to_lowercase(), String::new()),
+            };
+        }
+
+        let options = EmbedOptions {
+            values,
+            folder,
+            target_folder,
+        };
+
+        Some(self.render_options(lang, provider.as_ref(), &options))
+    }
+
+    fn render_options(
+        &self,
+        lang: &str,
+        provider: &(dyn EmbedProvider + Send + Sync),
+        options: &EmbedOptions,
+    ) -> Result<String, String> {
+        let mut data = Map::new();
+        for (key, value) in &options.values {
+            data.insert(key.clone(), json!(value));
+        }
+
+        let aspect = options.get("aspect").unwrap_or("16:9");
+        let (width, height) = aspect
+            .split_once([':', '/'])
+            .and_then(|(w, h)| Some((w.trim().parse::<f32>().ok()?, h.trim().parse::<f32>().ok()?)))
+            .filter(|(w, h)| *w > 0.0 && *h > 0.0)
+            .ok_or_else(|| format!("invalid aspect ratio {}", aspect))?;
+        data.insert(
+            "padding_top".to_string(),
+            json!(format!("{}%", (height / width * 10000.0).round() / 100.0)),
+        );
+
+        provider.data(options, &mut data)?;
+
+        self.templates
+            .render(lang, &Value::Object(data))
+            .map(|html| html.trim().to_string())
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,343 @@
+use atom_syndication::{Category, Content, Entry, Feed, Link, Person, Text};
+use chrono::{DateTime, Local};
+use regex::{Captures, Regex};
+use rss::{CategoryBuilder, Channel, ChannelBuilder, GuidBuilder, ImageBuilder, Item, ItemBuilder};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::sync::LazyLock;
+
+use crate::md2html::SelectedMetaImage;
+use crate::post::Post;
+
+/// Attributes holding URLs in rendered posts. `original_src` is the full-size
+/// image the lightbox opens.
+static URL_ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(\s)(src|href|poster|original_src|srcset)="([^"]*)""#).unwrap());
+
+/// Feed settings, read from `[rss]` in quipquick.toml.
+#[derive(Debug, Clone, Default)]
+pub struct FeedOptions {
+    /// Include the rendered post in `content:encoded`, not only its description.
+    pub full_content: bool,
+    /// Only the most recent posts go into a feed. All of them when not set.
+    pub max_items: Option<usize>,
+}
+
+impl FeedOptions {
+    /// Reads `[rss]` from quipquick.toml, with defaults for what isn't set.
+    pub fn from_config(config: &toml::Value) -> Result<Self, String> {
+        let mut feed_options = FeedOptions::default();
+        let Some(config) = config.get("rss").and_then(|value| value.as_table()) else {
+            return Ok(feed_options);
+        };
+
+        if let Some(full_content) = config.get("full_content").and_then(|value| value.as_bool()) {
+            feed_options.full_content = full_content;
+        }
+        if let Some(max_items) = config.get("max_items") {
+            match max_items.as_integer() {
+                Some(max_items) if max_items > 0 => {
+                    feed_options.max_items = Some(max_items as usize)
+                }
+                _ => return Err("Rss max_items should be a positive number.".to_string()),
+            }
+        }
+
+        Ok(feed_options)
+    }
+}
+
+/// A feed's channel: the whole blog, or one tag.
+pub struct FeedInfo {
+    pub title: String,
+    pub link: String,
+    pub description: String,
+}
+
+/// A feed advertised by pages with `<link rel="alternate">`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedLink {
+    /// The MIME type, e.g. `application/atom+xml`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub href: String,
+}
+
+/// `https://example.com/blog` becomes `https://example.com`.
+fn origin(blog_url: &str) -> &str {
+    let host_start = blog_url.find("://").map(|i| i + 3).unwrap_or(0);
+    match blog_url[host_start..].find('/') {
+        Some(path_start) => &blog_url[..host_start + path_start],
+        None => blog_url,
+    }
+}
+
+/// Resolves a URL from a post's page, where `post_url` ends with a slash.
+fn absolute_url(url: &str, post_url: &str, origin: &str) -> String {
+    if url.contains("://")
+        || url.starts_with("//")
+        || url.starts_with("mailto:")
+        || url.starts_with("data:")
+    {
+        url.to_string()
+    } else if url.starts_with('/') {
+        format!("{}{}", origin, url)
+    } else {
+        format!("{}{}", post_url, url)
+    }
+}
+
+/// Feed readers don't show posts from their page, so the relative URLs of
+/// images, media and links in a post's html have to be made absolute.
+pub fn absolute_urls(html: &str, post_url: &str, blog_url: &str) -> String {
+    let origin = origin(blog_url);
+
+    URL_ATTRIBUTE
+        .replace_all(html, |c: &Captures| {
+            let value = if &c[2] == "srcset" {
+                c[3].split(',')
+                    .map(|candidate| {
+                        let candidate = candidate.trim();
+                        match candidate.split_once(' ') {
+                            Some((url, descriptor)) => {
+                                format!("{} {}", absolute_url(url, post_url, origin), descriptor)
+                            }
+                            None => absolute_url(candidate, post_url, origin),
+                        }
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            } else {
+                absolute_url(&c[3], post_url, origin)
+            };
+
+            format!("{}{}=\"{}\"", &c[1], &c[2], value)
+        })
+        .to_string()
+}
+
+fn feed_item(post: &Post, blog_url: &str, options: &FeedOptions) -> Item {
+    let permanent_link = format!("{}/{}", blog_url, post.src);
+    let guid = GuidBuilder::default().value(permanent_link.clone()).build();
+
+    // Tags are plain categories, categories are paths in the blog's tree.
+    let categories = post
+        .tags
+        .iter()
+        .map(|t| CategoryBuilder::default().name(t.tag.clone()).build())
+        .chain(post.categories.iter().map(|c| {
+            CategoryBuilder::default()
+                .name(c.path.clone())
+                .domain(Some(format!("{}/categories/", blog_url)))
+                .build()
+        }))
+        .collect::<Vec<_>>();
+
+    ItemBuilder::default()
+        .title(Some(titlecase::titlecase(&post.title)))
+        .link(Some(permanent_link.clone()))
+        .description(Some(post.description.clone()))
+        .comments(post.discussion_url.clone())
+        .guid(Some(guid))
+        .pub_date(Some(post.date.to_rfc2822()))
+        .categories(categories)
+        .content(
+            options
+                .full_content
+                .then(|| absolute_urls(&post.md, &format!("{}/", permanent_link), blog_url)),
+        )
+        .build()
+}
+
+/// An RSS channel for `posts`, which are sorted newest first.
+pub fn rss_channel(
+    info: FeedInfo,
+    posts: &[Post],
+    blog_url: &str,
+    logo: Option<&SelectedMetaImage>,
+    options: &FeedOptions,
+) -> Channel {
+    let items: Vec<Item> = feed_posts(posts, options)
+        .iter()
+        .map(|post| feed_item(post, blog_url, options))
+        .collect();
+
+    let image = logo.map(|l| {
+        ImageBuilder::default()
+            .url(format!("{}/{}", blog_url, l.url))
+            .title(info.title.clone())
+            .link(info.link.clone())
+            .build()
+    });
+
+    ChannelBuilder::default()
+        .title(info.title)
+        .link(info.link)
+        .description(info.description)
+        .items(items)
+        .image(image)
+        .build()
+}
+
+/// The posts a feed lists, newest first.
+fn feed_posts<'a>(posts: &'a [Post], options: &FeedOptions) -> &'a [Post] {
+    &posts[..options.max_items.unwrap_or(posts.len()).min(posts.len())]
+}
+
+/// An Atom feed for `posts`, which are sorted newest first. Atom requires an
+/// author, the blog's title stands in when there is none. It also requires an
+/// update time, which is `build_time` when there are no posts.
+pub fn atom_feed(
+    info: FeedInfo,
+    feed_url: &str,
+    posts: &[Post],
+    blog_url: &str,
+    author: Option<&str>,
+    options: &FeedOptions,
+    build_time: DateTime<Local>,
+) -> Feed {
+    let person = |name: &str| Person {
+        name: name.to_string(),
+        ..Default::default()
+    };
+
+    let entries: Vec<Entry> = feed_posts(posts, options)
+        .iter()
+        .map(|post| {
+            let permanent_link = format!("{}/{}", blog_url, post.src);
+            Entry {
+                title: Text::plain(titlecase::titlecase(&post.title)),
+                id: permanent_link.clone(),
+                updated: post.last_modified().fixed_offset(),
+                published: Some(post.date.fixed_offset()),
+                authors: post.author.as_deref().map(person).into_iter().collect(),
+                categories: post
+                    .tags
+                    .iter()
+                    .map(|t| Category {
+                        term: t.slug.clone(),
+                        label: Some(t.tag.clone()),
+                        ..Default::default()
+                    })
+                    .collect(),
+                links: vec![Link {
+                    href: permanent_link.clone(),
+                    ..Default::default()
+                }],
+                summary: (!post.description.is_empty())
+                    .then(|| Text::plain(post.description.clone())),
+                content: options.full_content.then(|| Content {
+                    value: Some(absolute_urls(
+                        &post.md,
+                        &format!("{}/", permanent_link),
+                        blog_url,
+                    )),
+                    content_type: Some("html".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    Feed {
+        title: Text::plain(info.title.clone()),
+        id: format!("{}/", info.link.trim_end_matches('/')),
+        updated: posts
+            .iter()
+            .map(|post| post.last_modified())
+            .max()
+            .unwrap_or(build_time)
+            .fixed_offset(),
+        authors: vec![person(author.unwrap_or(&info.title))],
+        links: vec![
+            Link {
+                href: info.link,
+                ..Default::default()
+            },
+            Link {
+                href: feed_url.to_string(),
+                rel: "self".to_string(),
+                mime_type: Some("application/atom+xml".to_string()),
+                ..Default::default()
+            },
+        ],
+        subtitle: Some(Text::plain(info.description)),
+        entries,
+        ..Default::default()
+    }
+}
+
+/// A JSON Feed 1.1 for `posts`, which are sorted newest first.
+pub fn json_feed(
+    info: FeedInfo,
+    feed_url: &str,
+    posts: &[Post],
+    blog_url: &str,
+    author: Option<&str>,
+    options: &FeedOptions,
+) -> String {
+    let items: Vec<Value> = feed_posts(posts, options)
+        .iter()
+        .map(|post| {
+            let permanent_link = format!("{}/{}", blog_url, post.src);
+            let mut item = Map::new();
+            item.insert("id".to_string(), json!(permanent_link));
+            item.insert("url".to_string(), json!(permanent_link));
+            item.insert("title".to_string(), json!(titlecase::titlecase(&post.title)));
+            if !post.description.is_empty() {
+                item.insert("summary".to_string(), json!(post.description));
+            }
+
+            // Items need content, the description is used without full content.
+            if options.full_content {
+                item.insert(
+                    "content_html".to_string(),
+                    json!(absolute_urls(&post.md, &format!("{}/", permanent_link), blog_url)),
+                );
+            } else {
+                item.insert("content_text".to_string(), json!(post.description));
+            }
+
+            item.insert("date_published".to_string(), json!(post.date.to_rfc3339()));
+            if post.updated.is_some() {
+                item.insert(
+                    "date_modified".to_string(),
+                    json!(post.last_modified().to_rfc3339()),
+                );
+            }
+            if let Some(image) = &post.meta_img {
+                item.insert("image".to_string(), json!(format!("{}/{}", blog_url, image)));
+            }
+            if !post.tags.is_empty() {
+                item.insert(
+                    "tags".to_string(),
+                    json!(post.tags.iter().map(|t| &t.tag).collect::<Vec<_>>()),
+                );
+            }
+            if let Some(author) = &post.author {
+                item.insert("authors".to_string(), json!([{ "name": author }]));
+            }
+
+            Value::Object(item)
+        })
+        .collect();
+
+    let mut feed = json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": info.title,
+        "home_page_url": info.link,
+        "feed_url": feed_url,
+        "description": info.description,
+        "items": items,
+    });
+
+    if let Some(author) = author {
+        feed.as_object_mut()
+            .unwrap()
+            .insert("authors".to_string(), json!([{ "name": author }]));
+    }
+
+    serde_json::to_string_pretty(&feed).unwrap()
+}
@@ -1,11 +1,39 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct FrontmatterInfo {
     pub title: String,
     pub date: String,
+    /// When the post was last revised, used for the sitemap's `lastmod`.
+    #[serde(default)]
+    pub updated: Option<String>,
     #[serde(default)]
     pub description: String,
+    /// Overrides the blog's `author` for this post.
+    #[serde(default)]
+    pub author: Option<String>,
     #[serde(default)]
     pub tags: Vec<String>,
+    /// Paths in the blog's category tree, e.g. `graphics/webgpu`.
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// The series the post is a part of, e.g. `WebGPU Tutorial`.
+    #[serde(default)]
+    pub series: Option<String>,
+    /// The post's place in its series. Parts without one come last, by date.
+    #[serde(default)]
+    pub series_order: Option<u32>,
+    /// Show a table of contents above the post.
+    #[serde(default)]
+    pub toc: bool,
+    /// TeX macros for this post, added to the blog's `[math.macros]`.
+    #[serde(default)]
+    pub macros: BTreeMap<String, String>,
+    /// Published, but left out of listings, feeds and the sitemap.
+    #[serde(default)]
+    pub unlisted: bool,
+    /// Asks search engines not to index the post, and leaves it out of the sitemap.
+    #[serde(default)]
+    pub noindex: bool,
 }
\ No newline at end of file
@@ -1,3 +1,4 @@
+use crate::base_path::BasePath;
 use crate::publish::generate_google_analytics_id;
 use chrono::{DateTime, Datelike, Local};
 use dateparser::parse_with_timezone;
@@ -117,6 +118,9 @@ impl Serialize for Gallery {
     }
 }
 
+/// Returns the date of the newest image, which is the gallery's `lastmod` in
+/// the sitemap.
+#[allow(clippy::too_many_arguments)]
 pub fn generate_gallery(
     target_folder: &str,
     gallery_path: &str,
@@ -126,10 +130,11 @@ pub fn generate_gallery(
     blog_url: &str,
     quipquick_version: &str,
     google_analytics_id: &str,
-) {
+    base_path: &BasePath,
+) -> Option<DateTime<Local>> {
     if gallery_path == "tags" {
         println!("There shouldn't be a gallery folder named tags");
-        return;
+        return None;
     }
 
     let target_folder_exists =
@@ -137,20 +142,22 @@ pub fn generate_gallery(
 
     if !target_folder_exists {
         fs::create_dir(format!("{}/{}", target_folder, gallery_path).as_str())
-            .expect(format!("Unable to create gallery folder: {}.", &gallery_path).as_str());
+            .unwrap_or_else(|_| panic!("Unable to create gallery folder: {}.", &gallery_path));
     }
 
     let contents = fs::read_to_string(format!("{}/content.toml", gallery_path).as_str())
         .expect("Should have been able to read the file");
 
-    let value = match contents.parse::<Value>() {
+    let value = match toml::from_str::<Value>(&contents) {
         Err(error) => {
-            println!("Toml Parsing Error: {}", error.to_string());
-            return;
+            println!("Toml Parsing Error: {}", error);
+            return None;
         }
         Ok(value) => value,
     };
 
+    let mut newest: Option<DateTime<Local>> = None;
+
     if let toml::Value::Array(galleries) = value.get("galleries").unwrap() {
         for g in galleries {
             if let toml::Value::Table(gallery) = g {
@@ -216,8 +223,8 @@ pub fn generate_gallery(
 
                         image_list.push(Image {
                             date: d.into(),
-                            title: title,
-                            file: file,
+                            title,
+                            file,
                             preview: preview_file,
                         });
                     }
@@ -225,14 +232,18 @@ pub fn generate_gallery(
 
                 image_list.sort_by(|a, b| {
                     if a.date < b.date {
-                        return Ordering::Greater;
+                        Ordering::Greater
                     } else if a.date == b.date {
-                        return Ordering::Equal;
+                        Ordering::Equal
                     } else {
-                        return Ordering::Less;
+                        Ordering::Less
                     }
                 });
 
+                if let Some(img) = image_list.first() {
+                    newest = newest.max(Some(img.date));
+                }
+
                 for img in &image_list {
                     // Copy video file
                     let copy_from = format!("{}/{}", gallery_path, img.file);
@@ -260,10 +271,11 @@ pub fn generate_gallery(
                     blog_description: blog_description.to_owned(),
                     blog_url:blog_url.to_owned(),
                     quipquick_version: quipquick_version.to_owned(),
-                    google_analytics: generate_google_analytics_id(&google_analytics_id)
+                    google_analytics: generate_google_analytics_id(google_analytics_id)
                 };
 
-                let reg = Handlebars::new();
+                let mut reg = Handlebars::new();
+                reg.register_helper("url", Box::new(base_path.clone()));
                 let gallery_template = fs::read_to_string("template/gallery.html")
                     .expect("Should have been able to read the file");
 
@@ -275,4 +287,6 @@ pub fn generate_gallery(
             }
         }
     }
+
+    newest
 }
@@ -0,0 +1,70 @@
+use std::path::Path;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// Build-time syntax highlighter. Code blocks are turned into class-annotated
+/// spans, and the theme's colors live in a separate stylesheet.
+pub struct Highlighter {
+    syntax_set: SyntaxSet,
+    theme: Theme,
+}
+
+impl Highlighter {
+    /// `theme` is either the name of a built-in theme (e.g. `base16-ocean.dark`)
+    /// or a path to a `.tmTheme` file.
+    pub fn new(theme: &str) -> Result<Self, String> {
+        let theme = if theme.ends_with(".tmTheme") {
+            ThemeSet::get_theme(Path::new(theme))
+                .map_err(|e| format!("Unable to load theme {}: {}", theme, e))?
+        } else {
+            let mut themes = ThemeSet::load_defaults();
+            match themes.themes.remove(theme) {
+                Some(t) => t,
+                None => {
+                    return Err(format!(
+                        "Unknown highlight theme {}. Available themes: {}.",
+                        theme,
+                        themes
+                            .themes
+                            .keys()
+                            .cloned()
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ));
+                }
+            }
+        };
+
+        Ok(Highlighter {
+            syntax_set: SyntaxSet::load_defaults_newlines(),
+            theme,
+        })
+    }
+
+    /// Returns `None` when the language isn't known, so the caller can fall back to
+    /// plain escaped code.
+    pub fn highlight(&self, code: &str, lang: &str) -> Option<String> {
+        let syntax = self.syntax_set.find_syntax_by_token(lang)?;
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &self.syntax_set, CLASS_STYLE);
+
+        for line in LinesWithEndings::from(code) {
+            if generator
+                .parse_html_for_line_which_includes_newline(line)
+                .is_err()
+            {
+                return None;
+            }
+        }
+
+        Some(generator.finalize())
+    }
+
+    pub fn css(&self) -> String {
+        css_for_theme_with_class_style(&self.theme, CLASS_STYLE).unwrap()
+    }
+}
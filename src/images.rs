@@ -0,0 +1,270 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::fs;
+use std::path::Path;
+
+use crate::cache::BuildCache;
+
+/// How post images are resized, read from `[images]` in quipquick.toml.
+#[derive(Debug, Clone)]
+pub struct ImageOptions {
+    /// Images are displayed at most this wide...
+    pub max_width: u32,
+    /// ...and this tall.
+    pub max_height: u32,
+    /// Widths generated for `srcset`, as long as they're smaller than the original.
+    pub widths: Vec<u32>,
+    /// Also generate WebP versions, offered through `<picture>`.
+    pub webp: bool,
+    pub webp_quality: f32,
+    /// The `sizes` attribute. Defaults to the displayed width, or the full
+    /// viewport on narrower screens.
+    pub sizes: Option<String>,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            max_width: 768,
+            max_height: 400,
+            widths: vec![480, 768, 1536],
+            webp: false,
+            webp_quality: 80.0,
+            sizes: None,
+        }
+    }
+}
+
+impl ImageOptions {
+    /// Reads `[images]` from quipquick.toml, with defaults for what isn't set.
+    pub fn from_config(config: &toml::Value) -> Result<Self, String> {
+        let mut images = ImageOptions::default();
+        let Some(config) = config.get("images").and_then(|value| value.as_table()) else {
+            return Ok(images);
+        };
+
+        let positive = |value: &toml::Value| {
+            value
+                .as_integer()
+                .filter(|n| *n > 0 && *n <= u32::MAX as i64)
+                .map(|n| n as u32)
+        };
+
+        for (key, size) in [
+            ("max_width", &mut images.max_width),
+            ("max_height", &mut images.max_height),
+        ] {
+            if let Some(value) = config.get(key) {
+                *size = positive(value)
+                    .ok_or_else(|| format!("images.{} must be a positive integer.", key))?;
+            }
+        }
+        if let Some(widths) = config.get("widths") {
+            images.widths = widths
+                .as_array()
+                .and_then(|widths| widths.iter().map(positive).collect::<Option<Vec<u32>>>())
+                .ok_or("images.widths must be positive integers.")?;
+        }
+        if let Some(webp) = config.get("webp").and_then(|value| value.as_bool()) {
+            images.webp = webp;
+        }
+        if let Some(quality) = config.get("webp_quality") {
+            match quality.as_integer() {
+                Some(quality) if (0..=100).contains(&quality) => {
+                    images.webp_quality = quality as f32
+                }
+                _ => return Err("images.webp_quality must be an integer from 0 to 100.".to_string()),
+            }
+        }
+        images.sizes = config
+            .get("sizes")
+            .and_then(|value| value.as_str())
+            .map(|sizes| sizes.to_string());
+
+        Ok(images)
+    }
+}
+
+/// The generated files for an image, relative to the post folder.
+pub struct ResponsiveImage {
+    /// Fallback for browsers without `srcset` support, at least as wide as the
+    /// displayed image when possible.
+    pub src: String,
+    pub srcset: String,
+    pub webp_srcset: Option<String>,
+    pub sizes: String,
+    /// Displayed size, used for the `width` and `height` attributes.
+    pub width: u32,
+    pub height: u32,
+    /// Build cache entries the image's files came from.
+    pub cache_keys: Vec<String>,
+}
+
+/// `photos/cat.jpg` becomes `photos/cat-480w.jpg`.
+fn variant_name(url: &str, width: u32, extension: Option<&str>) -> String {
+    let path = Path::new(url);
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    let extension = extension
+        .map(|e| e.to_string())
+        .or_else(|| path.extension().map(|e| e.to_string_lossy().to_string()));
+
+    let name = match extension {
+        Some(extension) => format!("{}-{}w.{}", stem, width, extension),
+        None => format!("{}-{}w", stem, width),
+    };
+
+    match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(parent) => format!("{}/{}", parent.to_string_lossy(), name),
+        None => name,
+    }
+}
+
+fn save_webp(img: &DynamicImage, path: &str, quality: f32) {
+    let rgba = DynamicImage::ImageRgba8(img.to_rgba8());
+    let encoded = webp::Encoder::from_image(&rgba).unwrap().encode(quality);
+    fs::write(path, &*encoded).unwrap();
+}
+
+/// Images are only decoded when something has to be generated.
+fn decoded<'a>(img: &'a mut Option<DynamicImage>, bytes: &[u8], source: &str) -> &'a DynamicImage {
+    img.get_or_insert_with(|| {
+        image::load_from_memory(bytes)
+            .unwrap_or_else(|e| panic!("Unable to decode image {}: {}", source, e))
+    })
+}
+
+/// Writes the resized versions of `{folder}/{url}` to the post's target folder,
+/// reusing earlier results from `cache`. The original, which is copied
+/// separately, stands in for any configured width it isn't wider than.
+pub fn generate_responsive_image(
+    url: &str,
+    folder: &str,
+    target_folder: &str,
+    options: &ImageOptions,
+    cache: &BuildCache,
+) -> ResponsiveImage {
+    let source = format!("{}/{}", folder, url);
+    let bytes = fs::read(&source).unwrap_or_else(|_| panic!("Image {} is not found.", source));
+    let hash = BuildCache::hash(&bytes);
+    let mut img: Option<DynamicImage> = None;
+    let mut cache_keys = Vec::new();
+
+    let dimensions_key = format!("{}.dimensions", hash);
+    let (source_width, source_height) = match cache
+        .get_string(&dimensions_key)
+        .and_then(|d| d.split_once('x').map(|(w, h)| (w.parse(), h.parse())))
+    {
+        Some((Ok(w), Ok(h))) => (w, h),
+        _ => {
+            let img = decoded(&mut img, &bytes, &source);
+            cache.put_string(&dimensions_key, &format!("{}x{}", img.width(), img.height()));
+            (img.width(), img.height())
+        }
+    };
+    cache_keys.push(dimensions_key);
+
+    let shrink_ratio = (options.max_width as f32 / source_width as f32)
+        .min(options.max_height as f32 / source_height as f32)
+        .min(1.0);
+    let width = (source_width as f32 * shrink_ratio).round() as u32;
+    let height = (source_height as f32 * shrink_ratio).round() as u32;
+
+    let mut widths: Vec<u32> = options
+        .widths
+        .iter()
+        .copied()
+        .filter(|w| *w < source_width)
+        .collect();
+    widths.sort();
+    widths.dedup();
+    if widths.is_empty() || options.widths.iter().any(|w| *w >= source_width) {
+        widths.push(source_width);
+    }
+
+    let extension = Path::new(url)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let mut srcset = Vec::new();
+    let mut webp_srcset = Vec::new();
+    let mut src = None;
+
+    for w in widths {
+        let h = (source_height as f32 * w as f32 / source_width as f32).round() as u32;
+        let mut resized: Option<DynamicImage> = None;
+
+        let file = if w == source_width {
+            url.to_string()
+        } else {
+            let file = variant_name(url, w, None);
+            let path = format!("{}/{}/{}", target_folder, folder, file);
+            let key = format!("{}-{}x{}.{}", hash, w, h, extension);
+
+            match cache.get(&key) {
+                Some(cached) => {
+                    fs::copy(cached, &path).unwrap();
+                }
+                None => {
+                    let r = decoded(&mut img, &bytes, &source).resize(w, h, FilterType::Lanczos3);
+                    r.save(&path).unwrap();
+                    cache.put(&key, Path::new(&path));
+                    resized = Some(r);
+                }
+            }
+            cache_keys.push(key);
+            file
+        };
+
+        if options.webp {
+            let webp_file = variant_name(url, w, Some("webp"));
+            let path = format!("{}/{}/{}", target_folder, folder, webp_file);
+            let key = format!("{}-{}x{}-q{}.webp", hash, w, h, options.webp_quality);
+
+            match cache.get(&key) {
+                Some(cached) => {
+                    fs::copy(cached, &path).unwrap();
+                }
+                None => {
+                    match &resized {
+                        Some(r) => save_webp(r, &path, options.webp_quality),
+                        None => {
+                            let img = decoded(&mut img, &bytes, &source);
+                            if w == source_width {
+                                save_webp(img, &path, options.webp_quality);
+                            } else {
+                                let r = img.resize(w, h, FilterType::Lanczos3);
+                                save_webp(&r, &path, options.webp_quality);
+                            }
+                        }
+                    }
+                    cache.put(&key, Path::new(&path));
+                }
+            }
+            cache_keys.push(key);
+            webp_srcset.push(format!("{} {}w", webp_file.replace(' ', "%20"), w));
+        }
+
+        if src.is_none() && w >= width {
+            src = Some(file.clone());
+        }
+        srcset.push(format!("{} {}w", file.replace(' ', "%20"), w));
+    }
+
+    ResponsiveImage {
+        src: src.unwrap_or_else(|| url.to_string()),
+        srcset: srcset.join(", "),
+        webp_srcset: if options.webp {
+            Some(webp_srcset.join(", "))
+        } else {
+            None
+        },
+        sizes: options
+            .sizes
+            .clone()
+            .unwrap_or_else(|| format!("(max-width: {}px) 100vw, {}px", width, width)),
+        width,
+        height,
+        cache_keys,
+    }
+}
@@ -2,13 +2,32 @@ use clap::{Parser, Subcommand};
 extern crate fs_extra;
 extern crate slugify;
 
+mod archive;
+mod base_path;
+mod cache;
+mod categories;
+mod callout;
+mod crossref;
+mod embed;
+mod feed;
 mod frontmatter;
+mod highlight;
+mod images;
+mod manifest;
+mod math;
 mod md2html;
 mod new;
+mod pagination;
 mod post;
 mod publish;
+mod related;
+mod search;
+mod series;
+mod serve;
+mod tags;
+mod sitemap;
 mod write;
-use publish::publish;
+use publish::{publish, PublishOptions};
 use write::new_post;
 mod gallery;
 
@@ -59,6 +78,24 @@ enum Commands {
         /// Blog url prefix
         #[arg(short, long)]
         prefix: Option<String>,
+
+        /// Rebuild everything instead of reusing posts and images from .quipquick-cache
+        #[arg(long, default_value_t = false)]
+        no_cache: bool,
+
+        /// Number of posts rendered in parallel, defaults to the number of CPUs
+        #[arg(short, long)]
+        jobs: Option<usize>,
+    },
+    /// Preview your blog, drafts included, rebuilding it as you edit.
+    Serve {
+        /// Port to serve the preview on
+        #[arg(short, long, default_value_t = 8000)]
+        port: u16,
+
+        /// Number of posts rendered in parallel, defaults to the number of CPUs
+        #[arg(short, long)]
+        jobs: Option<usize>,
     },
 }
 
@@ -87,9 +124,23 @@ fn main() {
         Commands::Pub {
             target,
             force_overwrite_theme,
-            prefix: _,
+            prefix,
+            no_cache,
+            jobs,
         } => {
-            publish(target, force_overwrite_theme);
+            if let Err(error) = publish(&PublishOptions {
+                target,
+                force_overwrite_theme,
+                no_cache,
+                jobs,
+                prefix,
+                preview: false,
+            }) {
+                println!("{}", error);
+            }
+        }
+        Commands::Serve { port, jobs } => {
+            serve::serve(port, jobs);
         }
         Commands::Write { title } => {
             new_post(title, false);
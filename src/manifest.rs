@@ -0,0 +1,440 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::cache::BuildCache;
+
+#[derive(Serialize, Deserialize)]
+struct ManifestFile {
+    target: String,
+    outputs: BTreeMap<String, String>,
+}
+
+/// Records the inputs every generated file came from, so a publish only rewrites
+/// outputs whose inputs changed and deletes the ones that are no longer generated.
+/// Paths are relative to the target folder.
+pub struct BuildManifest {
+    target_folder: String,
+    /// `None` when the target wasn't published with a manifest before.
+    previous: Option<BTreeMap<String, String>>,
+    /// Output path -> hash of its inputs.
+    outputs: BTreeMap<String, String>,
+    /// `true` with `pub --no-cache`: every output is generated again, but the
+    /// previous manifest still tells which files to remove.
+    regenerate: bool,
+    changed: usize,
+}
+
+/// Combines the inputs of an output into one hash.
+pub fn inputs_hash(inputs: &[&str]) -> String {
+    BuildCache::hash(inputs.join("\0").as_bytes())
+}
+
+fn collect_files(folder: &Path, files: &mut Vec<(String, u64, u128)>) {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else if let Ok(metadata) = entry.metadata() {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos())
+                .unwrap_or(0);
+            files.push((path.to_string_lossy().to_string(), metadata.len(), modified));
+        }
+    }
+}
+
+/// Hashes a post folder: the full content of `content.md`, and the size and
+/// modification time of everything else, which is mostly images.
+pub fn folder_hash(folder: &str) -> String {
+    let mut files = Vec::new();
+    collect_files(Path::new(folder), &mut files);
+    files.sort();
+
+    let mut inputs = vec![fs::read_to_string(format!("{}/content.md", folder)).unwrap_or_default()];
+    for (path, size, modified) in files {
+        inputs.push(format!("{} {} {}", path, size, modified));
+    }
+
+    inputs_hash(&inputs.iter().map(|i| i.as_str()).collect::<Vec<&str>>())
+}
+
+fn is_under(path: &str, folder: &str) -> bool {
+    path.strip_prefix(folder)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn is_post_file(path: &str, folder: &str, nested: &[&str]) -> bool {
+    is_under(path, folder) && !nested.iter().any(|n| is_under(path, n))
+}
+
+/// Each target has its own manifest, so a `serve` preview doesn't make the
+/// next `pub` start over.
+fn manifest_key(target_folder: &str) -> String {
+    format!("build-manifest-{}.json", &BuildCache::hash(target_folder.as_bytes())[..16])
+}
+
+impl BuildManifest {
+    /// The manifest is loaded even with `pub --no-cache`, which would
+    /// otherwise make every publish a first build that cleans the target.
+    pub fn load(target_folder: &str, cache: &BuildCache) -> Self {
+        let previous = cache
+            .read_string(&manifest_key(target_folder))
+            .and_then(|m| serde_json::from_str::<ManifestFile>(&m).ok())
+            .filter(|m| m.target == target_folder)
+            .map(|m| m.outputs);
+
+        BuildManifest {
+            target_folder: target_folder.to_string(),
+            previous,
+            outputs: BTreeMap::new(),
+            regenerate: !cache.reads(),
+            changed: 0,
+        }
+    }
+
+    /// Without a previous manifest there is no telling which files in the
+    /// target are stale, so the target has to be cleaned first.
+    pub fn is_first_build(&self) -> bool {
+        self.previous.is_none()
+    }
+
+    /// Whether `path` was generated from the same inputs last time and is still there.
+    pub fn is_fresh(&self, path: &str, inputs: &str) -> bool {
+        !self.regenerate
+            && self
+                .previous
+                .as_ref()
+                .and_then(|p| p.get(path))
+                .is_some_and(|previous| previous == inputs)
+            && Path::new(&self.target_folder).join(path).exists()
+    }
+
+    /// Writes the output of `render` to `path`, unless `path` is fresh. Files are
+    /// only touched when their content changes.
+    pub fn write<C: AsRef<[u8]>>(&mut self, path: &str, inputs: &str, render: impl FnOnce() -> C) {
+        if !self.is_fresh(path, inputs) {
+            let full_path = Path::new(&self.target_folder).join(path);
+            if let Some(parent) = full_path.parent() {
+                fs::create_dir_all(parent).unwrap();
+            }
+
+            let content = render();
+            if fs::read(&full_path).ok().as_deref() != Some(content.as_ref()) {
+                fs::write(&full_path, content).unwrap();
+                self.changed += 1;
+            }
+        }
+
+        self.record(path, inputs);
+    }
+
+    /// Writes `content` to `path` if it differs from what's there.
+    pub fn write_content<C: AsRef<[u8]>>(&mut self, path: &str, content: C) {
+        let inputs = BuildCache::hash(content.as_ref());
+        self.write(path, &inputs, || content);
+    }
+
+    /// Copies the file at `from` to `path` if it differs from what's there.
+    pub fn copy(&mut self, from: &str, path: &str) {
+        self.write_content(path, fs::read(from).unwrap());
+    }
+
+    /// Records a file that was generated outside of the manifest.
+    pub fn record(&mut self, path: &str, inputs: &str) {
+        self.outputs.insert(path.to_string(), inputs.to_string());
+    }
+
+    /// Records every file under `folder` in the target as an output.
+    pub fn record_folder(&mut self, folder: &str, inputs: &str) {
+        for file in self.files_in(folder) {
+            self.record(&file, inputs);
+        }
+    }
+
+    /// Keeps `path` from the previous publish as it is. Returns false if there is
+    /// nothing to keep.
+    pub fn keep(&mut self, path: &str) -> bool {
+        let previous = self.previous.as_ref().and_then(|p| p.get(path)).cloned();
+        match previous {
+            Some(inputs) if self.is_fresh(path, &inputs) => {
+                self.record(path, &inputs);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Paths of the files under `folder` in the target.
+    pub fn files_in(&self, folder: &str) -> Vec<String> {
+        let mut files = Vec::new();
+        collect_files(&Path::new(&self.target_folder).join(folder), &mut files);
+
+        let mut paths: Vec<String> = files
+            .into_iter()
+            .filter_map(|(path, _, _)| {
+                Path::new(&path)
+                    .strip_prefix(&self.target_folder)
+                    .ok()
+                    .map(|p| p.to_string_lossy().replace('\\', "/"))
+            })
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Paths of the files of the post in `folder`: everything under it except
+    /// the `nested` folders inside it, which are other posts.
+    pub fn post_files(&self, folder: &str, nested: &[&str]) -> Vec<String> {
+        self.files_in(folder)
+            .into_iter()
+            .filter(|path| is_post_file(path, folder, nested))
+            .collect()
+    }
+
+    /// Deletes the files of the post in `folder` that the previous publish
+    /// generated, before they are generated again. Files of the `nested` posts
+    /// inside it are kept.
+    pub fn remove_previous(&self, folder: &str, nested: &[&str]) {
+        let Some(previous) = &self.previous else {
+            return;
+        };
+
+        for path in previous.keys().filter(|p| is_post_file(p, folder, nested)) {
+            let _ = fs::remove_file(Path::new(&self.target_folder).join(path));
+        }
+    }
+
+    /// Outputs of the previous publish that weren't generated this time.
+    fn orphans(&self) -> Vec<String> {
+        match &self.previous {
+            Some(previous) => previous
+                .keys()
+                .filter(|p| !self.outputs.contains_key(*p))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether any file was written, or will be removed as an orphan.
+    pub fn has_changes(&self) -> bool {
+        self.changed > 0 || !self.orphans().is_empty()
+    }
+
+    /// Deletes outputs of the previous publish that weren't generated this time,
+    /// along with folders left empty.
+    pub fn remove_orphans(&mut self) {
+        for path in self.orphans() {
+            let full_path = Path::new(&self.target_folder).join(path);
+            if fs::remove_file(&full_path).is_ok() {
+                println!("Removing {}", full_path.display());
+                self.changed += 1;
+            }
+
+            let mut parent = full_path.parent();
+            while let Some(folder) = parent {
+                if folder == Path::new(&self.target_folder) || fs::remove_dir(folder).is_err() {
+                    break;
+                }
+                parent = folder.parent();
+            }
+        }
+    }
+
+    pub fn save(&self, cache: &BuildCache) {
+        let manifest = ManifestFile {
+            target: self.target_folder.clone(),
+            outputs: self.outputs.clone(),
+        };
+        cache.put_string(&manifest_key(&self.target_folder), &serde_json::to_string(&manifest).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    struct Blog {
+        _dir: TempDir,
+        target: String,
+        cache: String,
+    }
+
+    impl Blog {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let target = dir.path().join("out").to_string_lossy().to_string();
+            let cache = dir.path().join("cache").to_string_lossy().to_string();
+            fs::create_dir(&target).unwrap();
+            Blog {
+                _dir: dir,
+                target,
+                cache,
+            }
+        }
+
+        fn load(&self, read_cache: bool) -> BuildManifest {
+            BuildManifest::load(&self.target, &BuildCache::new(&self.cache, read_cache))
+        }
+
+        /// Publishes `outputs`, which are paths with the hash of their inputs.
+        fn publish(&self, outputs: &[(&str, &str)]) {
+            let cache = BuildCache::new(&self.cache, true);
+            let mut manifest = BuildManifest::load(&self.target, &cache);
+            for (path, inputs) in outputs {
+                manifest.write(path, inputs, || format!("{} {}", path, inputs));
+            }
+            manifest.remove_orphans();
+            manifest.save(&cache);
+        }
+
+        fn exists(&self, path: &str) -> bool {
+            Path::new(&self.target).join(path).exists()
+        }
+    }
+
+    #[test]
+    fn inputs_hash_keeps_inputs_apart() {
+        assert_eq!(inputs_hash(&["a", "bc"]), inputs_hash(&["a", "bc"]));
+        assert_ne!(inputs_hash(&["ab", "c"]), inputs_hash(&["a", "bc"]));
+    }
+
+    #[test]
+    fn folder_hash_follows_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().to_string_lossy().to_string();
+        let content = dir.path().join("content.md");
+
+        fs::write(&content, "# Hello").unwrap();
+        let hash = folder_hash(&folder);
+        assert_eq!(folder_hash(&folder), hash);
+
+        fs::write(&content, "# Hello again").unwrap();
+        assert_ne!(folder_hash(&folder), hash);
+    }
+
+    #[test]
+    fn first_build_then_incremental_build() {
+        let blog = Blog::new();
+        let manifest = blog.load(true);
+        assert!(manifest.is_first_build());
+        assert!(!manifest.is_fresh("index.html", "1"));
+
+        blog.publish(&[("index.html", "1")]);
+
+        let manifest = blog.load(true);
+        assert!(!manifest.is_first_build());
+        assert!(manifest.is_fresh("index.html", "1"));
+        assert!(!manifest.is_fresh("index.html", "2"));
+    }
+
+    #[test]
+    fn missing_output_is_not_fresh() {
+        let blog = Blog::new();
+        blog.publish(&[("index.html", "1")]);
+        fs::remove_file(Path::new(&blog.target).join("index.html")).unwrap();
+
+        assert!(!blog.load(true).is_fresh("index.html", "1"));
+    }
+
+    #[test]
+    fn fresh_outputs_are_not_rendered() {
+        let blog = Blog::new();
+        blog.publish(&[("index.html", "1")]);
+
+        let mut manifest = blog.load(true);
+        manifest.write("index.html", "1", || -> String { unreachable!() });
+        assert!(!manifest.has_changes());
+    }
+
+    #[test]
+    fn no_cache_keeps_manifest_but_regenerates() {
+        let blog = Blog::new();
+        blog.publish(&[("index.html", "1")]);
+
+        let manifest = blog.load(false);
+        assert!(!manifest.is_first_build());
+        assert!(!manifest.is_fresh("index.html", "1"));
+    }
+
+    #[test]
+    fn orphans_are_removed() {
+        let blog = Blog::new();
+        blog.publish(&[("index.html", "1"), ("tags/rust/index.html", "1")]);
+
+        blog.publish(&[("index.html", "2")]);
+        assert!(blog.exists("index.html"));
+        assert!(!blog.exists("tags/rust/index.html"));
+        assert!(!blog.exists("tags"));
+    }
+
+    #[test]
+    fn renamed_post_removes_only_its_old_folder() {
+        let blog = Blog::new();
+        blog.publish(&[
+            ("old/index.html", "1"),
+            ("old/cat.png", "1"),
+            ("other/index.html", "1"),
+        ]);
+
+        let mut manifest = blog.load(true);
+        manifest.write("new/index.html", "1", || "new");
+        manifest.write("other/index.html", "1", || "other");
+        assert_eq!(manifest.orphans(), vec!["old/cat.png", "old/index.html"]);
+
+        manifest.remove_orphans();
+        assert!(!blog.exists("old"));
+        assert!(blog.exists("new/index.html"));
+        assert!(blog.exists("other/index.html"));
+    }
+
+    #[test]
+    fn remove_previous_stays_in_its_folder() {
+        let blog = Blog::new();
+        blog.publish(&[
+            ("p1/index.html", "1"),
+            ("p1/cat.png", "1"),
+            ("p10/index.html", "1"),
+        ]);
+        fs::write(Path::new(&blog.target).join("p1/notes.txt"), "").unwrap();
+
+        blog.load(true).remove_previous("p1", &[]);
+        assert!(!blog.exists("p1/index.html"));
+        assert!(!blog.exists("p1/cat.png"));
+        assert!(blog.exists("p1/notes.txt"));
+        assert!(blog.exists("p10/index.html"));
+    }
+
+    #[test]
+    fn nested_post_keeps_its_files() {
+        let blog = Blog::new();
+        blog.publish(&[
+            ("a/index.html", "1"),
+            ("a/cat.png", "1"),
+            ("a/b/index.html", "1"),
+            ("a/b/dog.png", "1"),
+        ]);
+
+        let manifest = blog.load(true);
+        assert_eq!(
+            manifest.post_files("a", &["a/b"]),
+            vec!["a/cat.png", "a/index.html"]
+        );
+
+        manifest.remove_previous("a", &["a/b"]);
+        assert!(!blog.exists("a/cat.png"));
+        assert!(blog.exists("a/b/index.html"));
+        assert!(blog.exists("a/b/dog.png"));
+    }
+}
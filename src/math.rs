@@ -0,0 +1,70 @@
+use pulldown_latex::config::DisplayMode;
+use pulldown_latex::{push_mathml, Parser, RenderConfig, Storage};
+use std::collections::BTreeMap;
+
+/// Where TeX is turned into rendered math.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MathMode {
+    /// Emit the TeX source and let KaTeX render it in the browser.
+    Client,
+    /// Convert the TeX to MathML while publishing.
+    MathMl,
+}
+
+/// Turns macros in KaTeX's format (`"\\RR" = "\\mathbb{R}"`, with `#1`, `#2`...
+/// for arguments) into `\def`s that can be prepended to a formula.
+pub fn macro_definitions(macros: &BTreeMap<String, String>) -> String {
+    let mut definitions = String::new();
+
+    for (name, body) in macros {
+        let arguments = body
+            .as_bytes()
+            .windows(2)
+            .filter(|w| w[0] == b'#' && w[1].is_ascii_digit())
+            .map(|w| w[1] - b'0')
+            .max()
+            .unwrap_or(0);
+
+        definitions.push_str("\\def");
+        definitions.push_str(name);
+        for i in 1..=arguments {
+            definitions.push_str(format!("#{}", i).as_str());
+        }
+        definitions.push('{');
+        definitions.push_str(body);
+        definitions.push('}');
+    }
+
+    definitions
+}
+
+/// Converts TeX to MathML. `definitions` (see [`macro_definitions`]) are parsed
+/// ahead of the formula. The original TeX is kept as an annotation so it can still
+/// be copied from the page. All syntax errors are returned together.
+pub fn tex_to_mathml(tex: &str, definitions: &str, display: bool) -> Result<String, String> {
+    let source = format!("{}{}", definitions, tex);
+    let storage = Storage::new();
+    let events: Vec<_> = Parser::new(&source, &storage).collect();
+
+    let errors: Vec<String> = events
+        .iter()
+        .filter_map(|e| e.as_ref().err())
+        .map(|e| e.to_string())
+        .collect();
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+
+    let config = RenderConfig {
+        display_mode: if display {
+            DisplayMode::Block
+        } else {
+            DisplayMode::Inline
+        },
+        ..RenderConfig::with_annotation(tex)
+    };
+
+    let mut mathml = String::new();
+    push_mathml(&mut mathml, events.into_iter(), config).map_err(|e| e.to_string())?;
+    Ok(mathml)
+}
@@ -1,12 +1,25 @@
+use crate::cache::{BuildCache, CACHE_FOLDER};
+use crate::callout::{builtin_callout_types, parse_callout, CalloutType};
+use crate::crossref::{
+    collect_labels, push_text_with_refs, split_equation_label, split_label, table_caption, CrossRef,
+};
+use crate::embed::EmbedRegistry;
 use crate::frontmatter::FrontmatterInfo;
-use image::ImageReader;
+use crate::highlight::Highlighter;
+use crate::images::{generate_responsive_image, ImageOptions};
+use crate::math::{macro_definitions, tex_to_mathml, MathMode};
 use markdown::mdast::Node::{
-    self, Blockquote, Break, Code, Delete, Emphasis, FootnoteDefinition, FootnoteReference,
-    Heading, Html, Image, ImageReference, InlineCode, InlineMath, Link, LinkReference, List,
-    ListItem, Math, MdxFlowExpression, MdxJsxFlowElement, MdxJsxTextElement, MdxTextExpression,
-    MdxjsEsm, Paragraph, Root, Strong, Text, ThematicBreak, Toml, Yaml,
+    self, Blockquote, Break, Code, Definition, Delete, Emphasis, FootnoteDefinition,
+    FootnoteReference, Heading, Html, Image, ImageReference, InlineCode, InlineMath, Link,
+    LinkReference, List, ListItem, Math, MdxFlowExpression, MdxJsxFlowElement, MdxJsxTextElement,
+    MdxTextExpression, MdxjsEsm, Paragraph, Root, Strong, Table, TableCell, TableRow, Text,
+    ThematicBreak, Toml, Yaml,
 };
-use std::collections::{HashMap, HashSet};
+use markdown::mdast::{AlignKind, ReferenceKind};
+use markdown::unist::Position;
+use serde::{Deserialize, Serialize};
+use slugify::slugify;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Debug, Clone)]
 pub struct SelectedMetaImage {
@@ -22,125 +35,571 @@ pub struct Footnote {
     pub html: String,
 }
 
-pub fn render_markdown(
-    node: &Node,
+/// Target of a reference-style link or image, declared with `[id]: url "title"`.
+#[derive(Debug, Clone)]
+pub struct LinkDefinition {
+    pub url: String,
+    pub title: Option<String>,
+}
+
+/// An entry in a post's table of contents. Entries are nested under the closest
+/// preceding heading of a smaller depth.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub id: String,
+    pub title: String,
+    pub depth: u8,
+    pub children: Vec<TocEntry>,
+}
+
+/// Blog-wide rendering settings read from quipquick.toml.
+pub struct RenderOptions {
+    /// Append a `#` permalink to each heading, shown on hover.
+    pub heading_permalinks: bool,
+    /// Highlight code blocks at build time instead of loading highlight.js.
+    pub highlighter: Option<Highlighter>,
+    pub math_mode: MathMode,
+    /// TeX macros from `[math.macros]`, available in every post.
+    pub math_macros: BTreeMap<String, String>,
+    /// Built-in callout types plus those from `[callouts]`, keyed by lowercase name.
+    pub callout_types: HashMap<String, CalloutType>,
+    /// Providers for ` ```youtube `, ` ```video ` and other embed fences.
+    pub embeds: EmbedRegistry,
+    pub images: ImageOptions,
+    pub cache: BuildCache,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            heading_permalinks: true,
+            highlighter: None,
+            math_mode: MathMode::Client,
+            math_macros: BTreeMap::new(),
+            callout_types: builtin_callout_types(),
+            embeds: EmbedRegistry::new("template"),
+            images: ImageOptions::default(),
+            cache: BuildCache::new(CACHE_FOLDER, true),
+        }
+    }
+}
+
+/// Per-post state collected while walking the mdast.
+pub struct RenderContext<'a> {
+    pub options: &'a RenderOptions,
+    pub folder: &'a str,
+    pub target_folder: &'a str,
+    pub word_count: usize,
+    /// The post's text, for the search index.
+    pub text: String,
+    pub frontmatter: FrontmatterInfo,
+    pub langs: HashSet<String>,
+    pub selected_meta_image: Option<SelectedMetaImage>,
+    pub footnotes: HashMap<String, Footnote>,
+    pub definitions: HashMap<String, LinkDefinition>,
+    pub heading_ids: HashMap<String, usize>,
+    pub headings: Vec<TocEntry>,
+    pub math_errors: usize,
+    pub labels: HashMap<String, CrossRef>,
+    /// Set while rendering a link's text, where references would nest links.
+    in_link: bool,
+    /// Blog macros merged with the post's own, which take precedence.
+    pub macros: BTreeMap<String, String>,
+    macro_definitions: String,
+    /// Build cache entries used by the post's images.
+    pub cache_keys: Vec<String>,
+    /// Warnings and errors, printed once the post is done so the output of posts
+    /// rendered in parallel doesn't interleave.
+    pub log: Vec<String>,
+}
+
+impl<'a> RenderContext<'a> {
+    pub fn new(
+        options: &'a RenderOptions,
+        folder: &'a str,
+        target_folder: &'a str,
+        selected_meta_image: Option<SelectedMetaImage>,
+    ) -> Self {
+        RenderContext {
+            options,
+            folder,
+            target_folder,
+            word_count: 0,
+            text: String::new(),
+            frontmatter: FrontmatterInfo::default(),
+            langs: HashSet::new(),
+            selected_meta_image,
+            footnotes: HashMap::new(),
+            definitions: HashMap::new(),
+            heading_ids: HashMap::new(),
+            headings: Vec::new(),
+            math_errors: 0,
+            labels: HashMap::new(),
+            in_link: false,
+            macros: options.math_macros.clone(),
+            macro_definitions: macro_definitions(&options.math_macros),
+            cache_keys: Vec::new(),
+            log: Vec::new(),
+        }
+    }
+
+    /// Returns a slug id for a heading that is unique within the post. Repeated
+    /// titles get `-1`, `-2`, ... appended, the same way GitHub does it.
+    fn unique_heading_id(&mut self, title: &str) -> String {
+        let mut base = slugify!(title);
+        if base.is_empty() {
+            base = String::from("section");
+        }
+
+        let mut id = base.clone();
+        while let Some(used) = self.heading_ids.get_mut(&id) {
+            *used += 1;
+            id = format!("{}-{}", base, used);
+        }
+        self.heading_ids.insert(id.clone(), 0);
+        id
+    }
+}
+
+fn insert_toc_entry(entries: &mut Vec<TocEntry>, entry: TocEntry) {
+    if let Some(last) = entries.last_mut()
+        && last.depth < entry.depth
+    {
+        insert_toc_entry(&mut last.children, entry);
+    } else {
+        entries.push(entry);
+    }
+}
+
+/// Nests the flat list of headings collected during rendering.
+pub fn build_toc(headings: Vec<TocEntry>) -> Vec<TocEntry> {
+    let mut toc = Vec::new();
+    for h in headings {
+        insert_toc_entry(&mut toc, h);
+    }
+    toc
+}
+
+fn render_children(children: &[Node], output: &mut String, ctx: &mut RenderContext) {
+    let mut caption_rendered = false;
+
+    for (index, n) in children.iter().enumerate() {
+        if caption_rendered {
+            caption_rendered = false;
+            continue;
+        }
+
+        // A table's caption is the paragraph after it, rendered inside the table.
+        if let Table(t) = n
+            && let Some(caption) = children.get(index + 1).and_then(table_caption)
+        {
+            render_table(t, Some(caption), output, ctx);
+            caption_rendered = true;
+        } else {
+            render_markdown(n, output, ctx);
+        }
+    }
+}
+
+/// Definitions can appear anywhere in a document, including after the references
+/// that use them, so they are gathered before rendering starts.
+fn collect_definitions(node: &Node, definitions: &mut HashMap<String, LinkDefinition>) {
+    if let Definition(d) = node {
+        // The first definition of an identifier wins, as in CommonMark.
+        definitions
+            .entry(d.identifier.clone())
+            .or_insert_with(|| LinkDefinition {
+                url: d.url.clone(),
+                title: d.title.clone(),
+            });
+    } else if let Some(children) = node.children() {
+        for n in children {
+            collect_definitions(n, definitions);
+        }
+    }
+}
+
+/// Reproduces the source text of a reference whose definition is missing.
+fn render_unresolved_reference(
+    children: &[Node],
+    reference_kind: &ReferenceKind,
+    label: &Option<String>,
+    output: &mut String,
+    ctx: &mut RenderContext,
+) {
+    output.push('[');
+    render_children(children, output, ctx);
+    output.push(']');
+    match reference_kind {
+        ReferenceKind::Full => {
+            output.push('[');
+            if let Some(label) = label {
+                output.push_str(html_escape::encode_text(label).to_string().as_str());
+            }
+            output.push(']');
+        }
+        ReferenceKind::Collapsed => output.push_str("[]"),
+        ReferenceKind::Shortcut => {}
+    }
+}
+
+fn render_link(
+    url: &str,
+    title: &Option<String>,
+    children: &[Node],
     output: &mut String,
-    folder: &str,
-    target_folder: &str,
-    count: &mut usize,
-    frontmatter: &mut FrontmatterInfo,
-    langs: &mut HashSet<String>,
-    selected_meta_image: &mut Option<SelectedMetaImage>,
-    footnotes: &mut HashMap<String, Footnote>,
+    ctx: &mut RenderContext,
 ) {
+    output.push_str(
+        format!(
+            "<a class=\"link\" href=\"{}\"",
+            html_escape::encode_double_quoted_attribute(url)
+        )
+        .as_str(),
+    );
+    if let Some(title) = title {
+        output.push_str(
+            format!(
+                " title=\"{}\"",
+                html_escape::encode_double_quoted_attribute(title)
+            )
+            .as_str(),
+        );
+    }
+    output.push_str(" target=\"_blank\">");
+    let in_link = std::mem::replace(&mut ctx.in_link, true);
+    render_children(children, output, ctx);
+    ctx.in_link = in_link;
+    output.push_str("</a>");
+}
+
+fn render_image(url: &str, alt: &str, output: &mut String, ctx: &mut RenderContext) {
+    let folder = ctx.folder;
+    let target_folder = ctx.target_folder;
+
+    let (alt, label) = split_label(alt);
+    let cross_ref = label
+        .as_ref()
+        .and_then(|l| ctx.labels.get(l).map(|r| (l.clone(), r.clone())));
+
+    if let Some((label, _)) = &cross_ref {
+        output.push_str(
+            format!(
+                "<div class=\"img-container\" id=\"{}\">",
+                html_escape::encode_double_quoted_attribute(label)
+            )
+            .as_str(),
+        );
+    } else {
+        output.push_str("<div class=\"img-container\">");
+    }
+
+    let alt_parts_before_escaping: Vec<&str> = alt.split('|').collect();
+    let alt_str = if !alt_parts_before_escaping.is_empty() {
+        titlecase::titlecase(alt_parts_before_escaping[0])
+    } else {
+        String::from("")
+    };
+    let sources: Vec<String> = alt_parts_before_escaping
+        .iter()
+        .skip(1)
+        .map(|&elem| html_escape::encode_text(elem).to_string())
+        .collect();
+
+    let mut sources_json = String::new();
+
+    for s in &sources {
+        sources_json.push_str(format!("\"{}\",", s).as_str());
+    }
+
+    if !sources_json.is_empty() {
+        sources_json.remove(sources_json.len() - 1);
+    }
+
+    let responsive = generate_responsive_image(
+        url,
+        folder,
+        target_folder,
+        &ctx.options.images,
+        &ctx.options.cache,
+    );
+    ctx.cache_keys.extend(responsive.cache_keys.iter().cloned());
+
+    let mut img_tag = format!(
+        "<img class=\"img\" onclick=\"openImage(this)\" src=\"{}\" srcset=\"{}\" sizes=\"{}\" width=\"{}\" height=\"{}\" loading=\"lazy\" decoding=\"async\" original_src=\"{}\" alt=\"{}\" sources='[{}]' />",
+        html_escape::encode_double_quoted_attribute(&responsive.src),
+        html_escape::encode_double_quoted_attribute(&responsive.srcset),
+        html_escape::encode_double_quoted_attribute(&responsive.sizes),
+        responsive.width,
+        responsive.height,
+        html_escape::encode_double_quoted_attribute(url),
+        &alt_str,
+        sources_json
+    );
+    if let Some(webp_srcset) = &responsive.webp_srcset {
+        img_tag = format!(
+            "<picture><source type=\"image/webp\" srcset=\"{}\" sizes=\"{}\" />{}</picture>",
+            html_escape::encode_double_quoted_attribute(webp_srcset),
+            html_escape::encode_double_quoted_attribute(&responsive.sizes),
+            img_tag
+        );
+    }
+    output.push_str(&img_tag);
+
+    let pixels: u32 = responsive.width * responsive.height;
+    let aspect_ratio = ((responsive.width as f32 / responsive.height as f32) - 1.0).abs();
+    let meta_url = format!("{}/{}", folder, responsive.src);
+    if let Some(si) = &mut ctx.selected_meta_image {
+        if si.aspect_ratio > aspect_ratio || si.pixels < pixels {
+            *si = SelectedMetaImage {
+                pixels,
+                aspect_ratio,
+                url: meta_url,
+            };
+        }
+    } else {
+        ctx.selected_meta_image = Some(SelectedMetaImage {
+            pixels,
+            aspect_ratio,
+            url: meta_url,
+        });
+    }
+
+    output.push_str("<div class=\"img-title\">");
+    if let Some((_, r)) = &cross_ref {
+        output.push_str(&r.caption_prefix());
+    }
+    output.push_str(&alt_str);
+
+    for (index, source) in sources.iter().enumerate() {
+        output.push_str(
+            format!(
+                "<a class=\"img-source\" target=\"_blank\" href=\"{}\">[SOURCE{}]</a>",
+                source,
+                if sources.len() > 1 {
+                    format!(" {}", index + 1)
+                } else {
+                    String::new()
+                }
+            )
+            .as_str(),
+        );
+    }
+    output.push_str("</div>");
+    output.push_str("</div>");
+
+    let copy_from = format!("{}/{}", folder, url);
+    let copy_to = format!("{}/{}/{}", target_folder, folder, url);
+
+    std::fs::copy(copy_from, copy_to).unwrap();
+}
+
+/// Converts TeX to MathML, reporting syntax errors with their location in the
+/// post. Broken math is kept as TeX source so the rest of the page still renders.
+fn render_mathml(
+    tex: &str,
+    display: bool,
+    position: &Option<Position>,
+    output: &mut String,
+    ctx: &mut RenderContext,
+) {
+    match tex_to_mathml(tex, &ctx.macro_definitions, display) {
+        Ok(mathml) => output.push_str(&mathml),
+        Err(error) => {
+            let (line, column) = position
+                .as_ref()
+                .map(|p| (p.start.line, p.start.column))
+                .unwrap_or((0, 0));
+            // The position is the math node's, so the expression is shown
+            // to tell several on the same line apart.
+            ctx.log.push(format!(
+                "TeX error in {}/content.md:{}:{} in `{}`: {}",
+                ctx.folder,
+                line,
+                column,
+                tex.trim(),
+                error
+            ));
+            ctx.math_errors += 1;
+
+            output.push_str("<code class=\"math-error\">");
+            output.push_str(html_escape::encode_text(tex).to_string().as_str());
+            output.push_str("</code>");
+        }
+    }
+}
+
+/// Renders a table, with its caption paragraph if it has one.
+fn render_table(
+    t: &markdown::mdast::Table,
+    caption: Option<(Vec<Node>, Option<String>)>,
+    output: &mut String,
+    ctx: &mut RenderContext,
+) {
+    // The first row of a GFM table is always the header row.
+    output.push_str("<div class=\"table-container\"><table class=\"table\"");
+    let cross_ref = caption
+        .as_ref()
+        .and_then(|(_, label)| label.as_ref())
+        .and_then(|label| ctx.labels.get(label).map(|r| (label.clone(), r.clone())));
+    if let Some((label, _)) = &cross_ref {
+        output.push_str(
+            format!(" id=\"{}\"", html_escape::encode_double_quoted_attribute(label)).as_str(),
+        );
+    }
+    output.push('>');
+
+    if let Some((children, _)) = &caption {
+        output.push_str("<caption class=\"table-caption\">");
+        if let Some((_, r)) = &cross_ref {
+            output.push_str(&r.caption_prefix());
+        }
+        render_children(children, output, ctx);
+        output.push_str("</caption>");
+    }
+
+    for (row_index, row) in t.children.iter().enumerate() {
+        if let TableRow(r) = row {
+            let cell_tag = if row_index == 0 {
+                output.push_str("<thead>");
+                "th"
+            } else {
+                if row_index == 1 {
+                    output.push_str("<tbody>");
+                }
+                "td"
+            };
+
+            output.push_str("<tr>");
+            for (column, cell) in r.children.iter().enumerate() {
+                if let TableCell(c) = cell {
+                    output.push_str(
+                        format!("<{}{}>", cell_tag, align_style(t.align.get(column)))
+                            .as_str(),
+                    );
+                    render_children(&c.children, output, ctx);
+                    output.push_str(format!("</{}>", cell_tag).as_str());
+                }
+            }
+            output.push_str("</tr>");
+
+            if row_index == 0 {
+                output.push_str("</thead>");
+            }
+        }
+    }
+
+    if t.children.len() > 1 {
+        output.push_str("</tbody>");
+    }
+
+    output.push_str("</table></div>");
+}
+
+fn align_style(align: Option<&AlignKind>) -> &'static str {
+    match align {
+        Some(AlignKind::Left) => " style=\"text-align: left\"",
+        Some(AlignKind::Right) => " style=\"text-align: right\"",
+        Some(AlignKind::Center) => " style=\"text-align: center\"",
+        _ => "",
+    }
+}
+
+pub fn render_markdown(node: &Node, output: &mut String, ctx: &mut RenderContext) {
     match node {
         Paragraph(p) => {
             output.push_str("<p>");
-            for n in &p.children {
-                render_markdown(
-                    n,
-                    output,
-                    folder,
-                    target_folder,
-                    count,
-                    frontmatter,
-                    langs,
-                    selected_meta_image,
-                    footnotes,
-                );
-            }
-
+            render_children(&p.children, output, ctx);
             output.push_str("</p>");
         }
         Text(t) => {
-            *count += words_count::count(&t.value).words;
-            output.push_str(&t.value);
+            ctx.word_count += words_count::count(&t.value).words;
+            ctx.text.push_str(&t.value);
+            ctx.text.push(' ');
+            if ctx.in_link {
+                output.push_str(html_escape::encode_text(&t.value).to_string().as_str());
+            } else {
+                push_text_with_refs(&t.value, &ctx.labels, ctx.folder, output, &mut ctx.log);
+            }
         }
         Root(r) => {
-            for n in &r.children {
-                render_markdown(
-                    n,
-                    output,
-                    folder,
-                    target_folder,
-                    count,
-                    frontmatter,
-                    langs,
-                    selected_meta_image,
-                    footnotes,
-                );
-            }
+            collect_definitions(node, &mut ctx.definitions);
+            ctx.labels = collect_labels(&r.children, ctx.folder, &mut ctx.log);
+            render_children(&r.children, output, ctx);
         }
-        Blockquote(b) => {
-            output.push_str("<blockquote>");
+        Blockquote(b) => match parse_callout(&b.children, &ctx.options.callout_types) {
+            Some(callout) => {
+                let mut title = String::new();
+                if callout.title.is_empty() {
+                    title.push_str(
+                        html_escape::encode_text(&callout.callout_type.title)
+                            .to_string()
+                            .as_str(),
+                    );
+                } else {
+                    render_children(&callout.title, &mut title, ctx);
+                }
 
-            for n in &b.children {
-                render_markdown(
-                    n,
-                    output,
-                    folder,
-                    target_folder,
-                    count,
-                    frontmatter,
-                    langs,
-                    selected_meta_image,
-                    footnotes,
-                );
+                if callout.callout_type.collapsible {
+                    output.push_str(
+                        format!(
+                            "<details class=\"callout callout-{}\"><summary class=\"callout-title\">{}</summary>",
+                            callout.kind, title
+                        )
+                        .as_str(),
+                    );
+                    render_children(&callout.children, output, ctx);
+                    output.push_str("</details>");
+                } else {
+                    output.push_str(
+                        format!(
+                            "<div class=\"callout callout-{}\" role=\"note\"><p class=\"callout-title\">{}</p>",
+                            callout.kind, title
+                        )
+                        .as_str(),
+                    );
+                    render_children(&callout.children, output, ctx);
+                    output.push_str("</div>");
+                }
             }
-
-            output.push_str("</blockquote>");
-        }
+            None => {
+                output.push_str("<blockquote>");
+                render_children(&b.children, output, ctx);
+                output.push_str("</blockquote>");
+            }
+        },
 
         FootnoteDefinition(f) => {
             let mut footnote_html: String = String::new();
 
-            for n in &f.children {
-                render_markdown(
-                    n,
-                    &mut footnote_html,
-                    folder,
-                    target_folder,
-                    count,
-                    frontmatter,
-                    langs,
-                    selected_meta_image,
-                    footnotes,
-                );
-            }
+            render_children(&f.children, &mut footnote_html, ctx);
 
-            if let Some(existing_f) = footnotes.get_mut(&f.identifier) {
+            if let Some(existing_f) = ctx.footnotes.get_mut(&f.identifier) {
                 existing_f.html = footnote_html;
             } else {
                 let footnote = Footnote {
                     id: f.identifier.clone(),
-                    count: footnotes.len() as i32 + 1,
+                    count: ctx.footnotes.len() as i32 + 1,
                     html: footnote_html,
                 };
-                footnotes.insert(f.identifier.clone(), footnote);
+                ctx.footnotes.insert(f.identifier.clone(), footnote);
             }
         }
         MdxJsxFlowElement(_) => {}
         MdxjsEsm(_) => {}
         List(l) => {
             if l.ordered {
-                output.push_str("<ol>");
+                match l.start {
+                    Some(start) if start != 1 => {
+                        output.push_str(format!("<ol start=\"{}\">", start).as_str())
+                    }
+                    _ => output.push_str("<ol>"),
+                }
             } else {
                 output.push_str("<ul>");
             }
 
-            for n in &l.children {
-                render_markdown(
-                    n,
-                    output,
-                    folder,
-                    target_folder,
-                    count,
-                    frontmatter,
-                    langs,
-                    selected_meta_image,
-                    footnotes,
-                );
-            }
+            render_children(&l.children, output, ctx);
 
             if l.ordered {
                 output.push_str("</ol>");
@@ -149,10 +608,15 @@ pub fn render_markdown(
             }
         }
         Toml(c) => {
-            println!("{:?}", c);
+            ctx.log.push(format!("{:?}", c));
         }
         Yaml(c) => {
-            *frontmatter = serde_yaml::from_str(&c.value).unwrap();
+            ctx.frontmatter = serde_yaml::from_str(&c.value).unwrap();
+
+            for (name, body) in &ctx.frontmatter.macros {
+                ctx.macros.insert(name.clone(), body.clone());
+            }
+            ctx.macro_definitions = macro_definitions(&ctx.macros);
         }
         Break(_) => {
             output.push_str("<br />");
@@ -163,41 +627,39 @@ pub fn render_markdown(
             output.push_str("</code>");
         }
         InlineMath(im) => {
-            output.push_str("<code class=\"language-math math-inline\">");
-            output.push_str(&im.value);
-            output.push_str("</code>");
+            if ctx.options.math_mode == MathMode::MathMl {
+                output.push_str("<span class=\"mathml-inline\">");
+                render_mathml(&im.value, false, &im.position, output, ctx);
+                output.push_str("</span>");
+            } else {
+                output.push_str("<code class=\"language-math math-inline\">");
+                output.push_str(html_escape::encode_text(&im.value).to_string().as_str());
+                output.push_str("</code>");
+            }
+        }
+        Delete(d) => {
+            output.push_str("<del>");
+            render_children(&d.children, output, ctx);
+            output.push_str("</del>");
         }
-        Delete(_) => {}
         Emphasis(e) => {
             output.push_str("<em>");
-            for n in &e.children {
-                render_markdown(
-                    n,
-                    output,
-                    folder,
-                    target_folder,
-                    count,
-                    frontmatter,
-                    langs,
-                    selected_meta_image,
-                    footnotes,
-                );
-            }
+            render_children(&e.children, output, ctx);
             output.push_str("</em>");
         }
         MdxTextExpression(_) => {}
         FootnoteReference(f) => {
-            let count = if !footnotes.contains_key(&f.identifier) {
-                let count = footnotes.len() as i32 + 1;
+            let count = if let Some(existing_f) = ctx.footnotes.get(&f.identifier) {
+                existing_f.count
+            } else {
+                let count = ctx.footnotes.len() as i32 + 1;
                 let footnote = Footnote {
                     id: f.identifier.clone(),
                     count,
                     html: String::new(),
                 };
-                footnotes.insert(f.identifier.clone(), footnote);
+                ctx.footnotes.insert(f.identifier.clone(), footnote);
                 count
-            } else {
-                footnotes.get(&f.identifier).unwrap().count
             };
 
             output.push_str(
@@ -212,183 +674,104 @@ pub fn render_markdown(
             output.push_str(&h.value);
         }
         Image(i) => {
-            output.push_str("<div class=\"img-container\">");
-
-            let img = ImageReader::open(format!("{}/{}", folder, i.url))
-                .expect(format!("Image {}/{} is not found.", folder, i.url).as_str())
-                .decode()
-                .unwrap();
-
-            let alt_parts_before_escaping: Vec<&str> = i.alt.split('|').collect();
-            let alt_str = if alt_parts_before_escaping.len() > 0 {
-                titlecase::titlecase(alt_parts_before_escaping[0])
-            } else {
-                String::from("")
-            };
-            let sources: Vec<String> = alt_parts_before_escaping
-                .iter()
-                .skip(1)
-                .map(|&elem| html_escape::encode_text(elem).to_string())
-                .collect();
-
-            let mut sources_json = String::new();
-
-            for s in &sources {
-                sources_json.push_str(format!("\"{}\",", s).as_str());
-            }
-
-            if sources_json.len() > 0 {
-                sources_json.remove(sources_json.len() - 1);
-            }
-
-            if img.width() > 768 || img.height() > 400 {
-                let shrink_ratio = (768.0 / img.width() as f32).min(400.0 / img.height() as f32);
-
-                let thumb = img.resize(
-                    (img.width() as f32 * shrink_ratio) as u32,
-                    (img.height() as f32 * shrink_ratio) as u32,
-                    image::imageops::FilterType::Lanczos3,
-                );
-
-                let thumb_path = format!("{}/{}/thumb_{}", target_folder, folder, i.url);
-                thumb.save(&thumb_path).unwrap();
-                output.push_str(
-                    format!(
-                        "<img class=\"img\" onclick=\"openImage(this)\" src=\"thumb_{}\" original_src=\"{}\" alt=\"{}\" sources='[{}]' />",
-                        &i.url,&i.url,&alt_str,sources_json
-                    )
-                    .as_str(),
-                );
-
-                let pixels: u32 = thumb.width() * thumb.height();
-                let aspect_ratio = ((thumb.width() as f32 / thumb.height() as f32) - 1.0).abs();
-                if let Some(si) = selected_meta_image {
-                    if si.aspect_ratio > aspect_ratio || si.pixels < pixels {
-                        *si = SelectedMetaImage {
-                            pixels,
-                            aspect_ratio,
-                            url: format!("{}/thumb_{}", folder, i.url),
-                        };
-                    }
-                } else {
-                    *selected_meta_image = Some(SelectedMetaImage {
-                        pixels,
-                        aspect_ratio,
-                        url: format!("{}/thumb_{}", folder, i.url),
-                    });
-                }
+            render_image(&i.url, &i.alt, output, ctx);
+        }
+        ImageReference(ir) => {
+            if let Some(d) = ctx.definitions.get(&ir.identifier).cloned() {
+                render_image(&d.url, &ir.alt, output, ctx);
             } else {
-                let img_path = format!("{}/{}", folder, i.url);
-                output.push_str(
-                    format!(
-                        "<img class=\"img\" onclick=\"openImage(this)\" src=\"{}\" alt=\"{}\" sources='[{}]' />",
-                        &i.url, &alt_str , sources_json
-                    )
-                    .as_str(),
-                );
-
-                let pixels: u32 = img.width() * img.height();
-                let aspect_ratio = ((img.width() as f32 / img.height() as f32) - 1.0).abs();
-                if let Some(si) = selected_meta_image {
-                    if si.aspect_ratio > aspect_ratio || si.pixels < pixels {
-                        *si = SelectedMetaImage {
-                            pixels,
-                            aspect_ratio,
-                            url: img_path,
-                        };
-                    }
-                } else {
-                    *selected_meta_image = Some(SelectedMetaImage {
-                        pixels,
-                        aspect_ratio,
-                        url: img_path,
-                    });
+                ctx.log.push(format!(
+                    "Warning: {}/content.md has no definition for image reference [{}].",
+                    ctx.folder, ir.identifier
+                ));
+                output.push_str("![");
+                output.push_str(html_escape::encode_text(&ir.alt).to_string().as_str());
+                output.push(']');
+                if ir.reference_kind == ReferenceKind::Full
+                    && let Some(label) = &ir.label
+                {
+                    output.push('[');
+                    output.push_str(html_escape::encode_text(label).to_string().as_str());
+                    output.push(']');
                 }
             }
-
-            output.push_str("<div class=\"img-title\">");
-            output.push_str(&alt_str);
-
-            for i in 0..sources.len() {
-                output.push_str(
-                    format!(
-                        "<a class=\"img-source\" target=\"_blank\" href=\"{}\">[SOURCE{}]</a>",
-                        sources[i],
-                        if sources.len() > 1 {
-                            format!(" {}", i + 1)
-                        } else {
-                            String::new()
-                        }
-                    )
-                    .as_str(),
-                );
-            }
-            output.push_str("</div>");
-            output.push_str("</div>");
-
-            let copy_from = format!("{}/{}", folder, i.url);
-            let copy_to = format!("{}/{}/{}", target_folder, folder, i.url);
-
-            std::fs::copy(copy_from, copy_to).unwrap();
         }
-        ImageReference(_) => {}
         MdxJsxTextElement(_) => {}
         Link(l) => {
             //println!("{:?}", l);
-            output.push_str(
-                format!("<a class=\"link\" href=\"{}\" target=\"_blank\">", &l.url).as_str(),
-            );
-            if let Some(title) = &l.title {
-                output.push_str(title);
-                *count += words_count::count(title).words;
-            }
-            for n in &l.children {
-                render_markdown(
-                    n,
+            render_link(&l.url, &l.title, &l.children, output, ctx);
+        }
+        LinkReference(lr) => {
+            if let Some(d) = ctx.definitions.get(&lr.identifier).cloned() {
+                render_link(&d.url, &d.title, &lr.children, output, ctx);
+            } else {
+                ctx.log.push(format!(
+                    "Warning: {}/content.md has no definition for link reference [{}].",
+                    ctx.folder, lr.identifier
+                ));
+                render_unresolved_reference(
+                    &lr.children,
+                    &lr.reference_kind,
+                    &lr.label,
                     output,
-                    folder,
-                    target_folder,
-                    count,
-                    frontmatter,
-                    langs,
-                    selected_meta_image,
-                    footnotes,
+                    ctx,
                 );
             }
-            output.push_str("</a>");
-        }
-        LinkReference(_) => {
-            println!("link ref");
         }
+        Definition(_) => {}
         Strong(s) => {
             output.push_str("<strong>");
-            for n in &s.children {
-                render_markdown(
-                    n,
-                    output,
-                    folder,
-                    target_folder,
-                    count,
-                    frontmatter,
-                    langs,
-                    selected_meta_image,
-                    footnotes,
-                );
-            }
+            render_children(&s.children, output, ctx);
             output.push_str("</strong>");
         }
         Code(c) => {
             if let Some(lang) = &c.lang {
-                if lang == "youtube" {
-                    //https://www.howtocanvas.com/create-amazing-pages-in-canvas/responsive-youtube-iframes
-                    output.push_str(format!("<div style=\"width: 100%; min-width: 320px; max-width: 800px;\"><div style=\"position: relative; width: 100%; overflow: hidden; padding-top: 56.25%;\"><iframe class=\"video\" src=\"https://www.youtube.com/embed/{}\" title=\"YouTube video player\" frameborder=\"0\" allow=\"accelerometer; autoplay; clipboard-write; encrypted-media; gyroscope; picture-in-picture; web-share\" allowfullscreen></iframe></div></div>", &c.value).as_str());
+                if let Some(embed) = ctx.options.embeds.render(
+                    lang,
+                    &c.value,
+                    ctx.folder,
+                    ctx.target_folder,
+                ) {
+                    match embed {
+                        Ok(html) => output.push_str(&html),
+                        Err(error) => {
+                            ctx.log.push(format!(
+                                "Warning: {}/content.md has an invalid {} embed: {}.",
+                                ctx.folder, lang, error
+                            ));
+                            output.push_str("<pre><code>");
+                            output.push_str(html_escape::encode_text(&c.value).to_string().as_str());
+                            output.push_str("</code></pre>");
+                        }
+                    }
+                } else if let Some(highlighter) = &ctx.options.highlighter {
+                    if let Some(highlighted) = highlighter.highlight(&c.value, lang) {
+                        output.push_str(
+                            format!(
+                                "<pre class=\"hl-code\"><code class=\"language-{} code-block\">",
+                                lang
+                            )
+                            .as_str(),
+                        );
+                        output.push_str(&highlighted);
+                    } else {
+                        ctx.log.push(format!(
+                            "Warning: {}/content.md uses unknown language {}, the code block is not highlighted.",
+                            ctx.folder, lang
+                        ));
+                        output.push_str(
+                            format!("<pre><code class=\"language-{} code-block\">", lang).as_str(),
+                        );
+                        output.push_str(html_escape::encode_text(&c.value).to_string().as_str());
+                    }
+                    output.push_str("</code></pre>");
                 } else {
                     output.push_str(
                         format!("<pre><code class=\"language-{} code-block\">", lang).as_str(),
                     );
                     output.push_str(html_escape::encode_text(&c.value).to_string().as_str());
                     output.push_str("</code></pre>");
-                    langs.insert(lang.clone());
+                    ctx.langs.insert(lang.clone());
                 }
             } else {
                 output.push_str("<pre><code>");
@@ -397,51 +780,90 @@ pub fn render_markdown(
             }
         }
         Math(m) => {
-            output.push_str(
-                "<p class=\"katex-display-counter\"><code class=\"language-math math-block\">",
-            );
-            output.push_str(&m.value);
-            output.push_str("</code></p>");
+            let (tex, label) = split_equation_label(&m.value);
+            let id = label
+                .map(|l| format!(" id=\"{}\"", html_escape::encode_double_quoted_attribute(&l)))
+                .unwrap_or_default();
+
+            if ctx.options.math_mode == MathMode::MathMl {
+                output.push_str(format!("<p class=\"mathml-display\"{}>", id).as_str());
+                render_mathml(&tex, true, &m.position, output, ctx);
+                output.push_str("</p>");
+            } else {
+                output.push_str(
+                    format!(
+                        "<p class=\"katex-display-counter\"{}><code class=\"language-math math-block\">",
+                        id
+                    )
+                    .as_str(),
+                );
+                output.push_str(html_escape::encode_text(&tex).to_string().as_str());
+                output.push_str("</code></p>");
+            }
         }
         MdxFlowExpression(_) => {}
         Heading(h) => {
-            output.push_str(format!("<h{} >", h.depth).as_str());
-            for n in &h.children {
-                render_markdown(
-                    n,
-                    output,
-                    folder,
-                    target_folder,
-                    count,
-                    frontmatter,
-                    langs,
-                    selected_meta_image,
-                    footnotes,
+            let title: String = h.children.iter().map(|n| n.to_string()).collect();
+            let id = ctx.unique_heading_id(&title);
+
+            output.push_str(format!("<h{} id=\"{}\">", h.depth, id).as_str());
+            render_children(&h.children, output, ctx);
+            if ctx.options.heading_permalinks {
+                output.push_str(
+                    format!(
+                        "<a class=\"heading-anchor\" href=\"#{}\" aria-label=\"Permalink\">#</a>",
+                        id
+                    )
+                    .as_str(),
                 );
             }
             output.push_str(format!("</h{}>", h.depth).as_str());
+
+            ctx.headings.push(TocEntry {
+                id,
+                title,
+                depth: h.depth,
+                children: Vec::new(),
+            });
         }
 
         ListItem(li) => {
-            output.push_str("<li>");
-            for n in &li.children {
-                render_markdown(
-                    n,
-                    output,
-                    folder,
-                    target_folder,
-                    count,
-                    frontmatter,
-                    langs,
-                    selected_meta_image,
-                    footnotes,
-                );
+            if let Some(checked) = li.checked {
+                output.push_str("<li class=\"task-list-item\">");
+                let checkbox = if checked {
+                    "<input type=\"checkbox\" class=\"task-list-checkbox\" disabled checked /> "
+                } else {
+                    "<input type=\"checkbox\" class=\"task-list-checkbox\" disabled /> "
+                };
+
+                // Keep the checkbox on the same line as the item's first paragraph.
+                match li.children.split_first() {
+                    Some((Paragraph(p), rest)) => {
+                        output.push_str("<p>");
+                        output.push_str(checkbox);
+                        render_children(&p.children, output, ctx);
+                        output.push_str("</p>");
+                        render_children(rest, output, ctx);
+                    }
+                    _ => {
+                        output.push_str(checkbox);
+                        render_children(&li.children, output, ctx);
+                    }
+                }
+            } else {
+                output.push_str("<li>");
+                render_children(&li.children, output, ctx);
             }
             output.push_str("</li>");
         }
-        ThematicBreak(_tb) => {}
+        ThematicBreak(_tb) => {
+            output.push_str("<hr />");
+        }
+        Table(t) => {
+            render_table(t, None, output, ctx);
+        }
         _ => {
-            println!("Unimplemented node. {:?}", &node);
+            ctx.log.push(format!("Unimplemented node. {:?}", &node));
         }
     };
 }
@@ -3,14 +3,12 @@ use inquire::{
     Confirm, Text,
 };
 
+use handlebars::Handlebars;
 use rust_embed::RustEmbed;
+use serde_json::json;
 use slugify::slugify;
-use std::io::LineWriter;
+use std::fs;
 use std::path::Path;
-use std::{
-    fs::{self, File},
-    io::Write,
-};
 
 #[derive(RustEmbed)]
 #[folder = "template_src/"]
@@ -28,10 +26,42 @@ pub fn populate_templates(base_folder: &str, force: bool) {
         }
     } else {
         fs::create_dir(&target_folder)
-            .expect(format!("Unable to create template folder: {}.", &target_folder).as_str());
+            .unwrap_or_else(|_| panic!("Unable to create template folder: {}.", &target_folder));
+    }
+
+    let embeds_folder = format!("{}/embeds", target_folder);
+    if !Path::new(&embeds_folder).exists() {
+        fs::create_dir(&embeds_folder)
+            .unwrap_or_else(|_| panic!("Unable to create template folder: {}.", &embeds_folder));
+    }
+
+    let partials_folder = format!("{}/partials", target_folder);
+    if !Path::new(&partials_folder).exists() {
+        fs::create_dir(&partials_folder)
+            .unwrap_or_else(|_| panic!("Unable to create template folder: {}.", &partials_folder));
     }
 
-    let files = ["post.html", "index.html", "style.css", "gallery.html"];
+    let files = [
+        "post.html",
+        "index.html",
+        "style.css",
+        "gallery.html",
+        "search.html",
+        "search.js",
+        "archive.html",
+        "tags.html",
+        "categories.html",
+        "partials/head.html",
+        "partials/header.html",
+        "partials/footer.html",
+        "embeds/youtube.html",
+        "embeds/vimeo.html",
+        "embeds/gist.html",
+        "embeds/codepen.html",
+        "embeds/video.html",
+        "embeds/audio.html",
+        "embeds/iframe.html",
+    ];
 
     for f in files {
         let file_path = format!("{}/{}", target_folder, f);
@@ -112,7 +142,7 @@ pub fn new_blog(
             }
         } else {
             fs::create_dir_all(&blog_folder)
-                .expect(format!("Unable to create blog folder: {}.", &blog_folder).as_str());
+                .unwrap_or_else(|_| panic!("Unable to create blog folder: {}.", &blog_folder));
         }
 
         let default_blog_target = if let Some(t) = target {
@@ -152,46 +182,27 @@ pub fn new_blog(
                 }
             }
         } else {
-            fs::create_dir_all(&full_blog_target).expect(
-                format!(
+            fs::create_dir_all(&full_blog_target).unwrap_or_else(|_| {
+                panic!(
                     "Unable to create blog target folder: {}.",
                     &full_blog_target
                 )
-                .as_str(),
-            );
+            });
         }
 
-        let file = File::create(format!("{}/quipquick.toml", &blog_folder)).unwrap();
-        let mut file = LineWriter::new(file);
-
-        file.write_all(format!("title = \"{}\"\n", &blog_title).as_bytes())
-            .unwrap();
-        file.write_all(b"# Your github repo\n").unwrap();
-        file.write_all(b"repo = \"https://github.com/shi-yan/QuipQuick\"\n")
-            .unwrap();
-        file.write_all(
-            b"# Url prefix if your blog is not deployed at the root. Need to have the slash /.\n",
-        )
-        .unwrap();
-        file.write_all(b"prefix = \"\"\n").unwrap();
-        file.write_all(format!("target = \"{}\"\n", &blog_target).as_bytes())
-            .unwrap();
-        file.write_all(b"# Blog url\n").unwrap();
-        file.write_all(b"url = \"http://localhost:8000\"\n")
-            .unwrap();
-        file.write_all(b"description = \"\"\"\n").unwrap();
-        file.write_all(b"Your blog's description.\"\"\"\n").unwrap();
-        file.write_all(b"# google_analytics_id = \"\"\n").unwrap();
-        file.write_all(b"# Your blog's github discussion url\n")
-            .unwrap();
-        file.write_all(
-            b"# discussion_url = \"https://github.com/shi-yan/shi-yan.github.io/discussions\"\n",
-        )
-        .unwrap();
-        file.write_all(b"logo = \"logo.png\"\n").unwrap();
-        file.write_all(b"\ncontent =[\"dummy_post\",]\n")
+        // The sample config is template_src/quipquick.toml, with the blog's
+        // title and target filled in as TOML strings.
+        let config = Template::get("quipquick.toml").unwrap();
+        let config = Handlebars::new()
+            .render_template(
+                &String::from_utf8_lossy(&config.data),
+                &json!({
+                    "title": toml::Value::String(blog_title.clone()).to_string(),
+                    "target": toml::Value::String(blog_target.clone()).to_string(),
+                }),
+            )
             .unwrap();
-        file.flush().unwrap();
+        fs::write(format!("{}/quipquick.toml", &blog_folder), config).unwrap();
 
         println!(
             "Your blog {} has been generated in {}.",
@@ -215,7 +226,7 @@ pub fn new_blog(
 
         if !Path::new(&dummy_folder).exists() {
             fs::create_dir_all(&dummy_folder)
-                .expect(format!("Unable to create dummy post folder: {}.", &dummy_folder).as_str());
+                .unwrap_or_else(|_| panic!("Unable to create dummy post folder: {}.", &dummy_folder));
         }
 
         let dummy_post_file = Template::get("content.md").unwrap();
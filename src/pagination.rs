@@ -0,0 +1,213 @@
+use serde_json::{Value, json};
+use std::ops::Range;
+
+use crate::base_path::BasePath;
+
+/// How listings are split into pages, read from `[pagination]` in quipquick.toml.
+#[derive(Debug, Clone)]
+pub struct PaginationOptions {
+    pub home_page_size: usize,
+    pub tag_page_size: usize,
+    /// `tag_page_size` unless it's set.
+    pub series_page_size: usize,
+    /// Where pages after the first go, relative to the listing's folder, with
+    /// `{n}` standing for the page number. A path ending in a slash is a folder
+    /// with an index.html, e.g. `page/{n}/`, and the first page is then linked
+    /// as the listing's folder.
+    pub page_path: String,
+}
+
+impl Default for PaginationOptions {
+    fn default() -> Self {
+        PaginationOptions {
+            home_page_size: 5,
+            tag_page_size: 5,
+            series_page_size: 5,
+            page_path: String::from("index{n}.html"),
+        }
+    }
+}
+
+impl PaginationOptions {
+    /// Reads `[pagination]` from quipquick.toml, with defaults for what isn't set.
+    pub fn from_config(config: &toml::Value) -> Result<Self, String> {
+        let mut pagination = PaginationOptions::default();
+        let Some(config) = config.get("pagination").and_then(|value| value.as_table()) else {
+            return Ok(pagination);
+        };
+
+        for (key, page_size) in [
+            ("home_page_size", &mut pagination.home_page_size),
+            ("tag_page_size", &mut pagination.tag_page_size),
+            ("series_page_size", &mut pagination.series_page_size),
+        ] {
+            if let Some(value) = config.get(key) {
+                match value.as_integer() {
+                    Some(size) if size > 0 => *page_size = size as usize,
+                    _ => return Err(format!("Pagination {} should be a positive number.", key)),
+                }
+            }
+        }
+
+        if !config.contains_key("series_page_size") {
+            pagination.series_page_size = pagination.tag_page_size;
+        }
+
+        if let Some(page_path) = config.get("page_path").and_then(|value| value.as_str()) {
+            if !page_path.contains("{n}") {
+                return Err(format!(
+                    "Pagination page_path {} should contain {{n}} for the page number.",
+                    page_path
+                ));
+            }
+            pagination.page_path = page_path.trim_start_matches('/').to_string();
+        }
+
+        Ok(pagination)
+    }
+}
+
+/// One page of a listing, such as the home page or a tag page.
+pub struct Page {
+    /// Where the page is written, relative to the target folder.
+    pub output_path: String,
+    /// The listing's items shown on this page.
+    pub range: Range<usize>,
+    /// Every page of the listing, for the `pages` template data.
+    pub pages: Vec<Value>,
+    pub prev: Option<String>,
+    pub next: Option<String>,
+}
+
+impl PaginationOptions {
+    /// Page `number`, counting from 1, of the listing in `folder`.
+    fn page_path(&self, folder: &str, number: usize) -> String {
+        let path = if number > 1 {
+            self.page_path.replace("{n}", &number.to_string())
+        } else if self.page_path.ends_with('/') {
+            String::new()
+        } else {
+            String::from("index.html")
+        };
+
+        if folder.is_empty() {
+            path
+        } else {
+            format!("{}/{}", folder, path)
+        }
+    }
+
+    /// Splits a listing of `item_count` items in `folder`, which is empty for
+    /// the home page, into pages of `page_size`. A listing always has a first
+    /// page, even when it's empty.
+    pub fn paginate(
+        &self,
+        folder: &str,
+        item_count: usize,
+        page_size: usize,
+        base_path: &BasePath,
+    ) -> Vec<Page> {
+        let page_count = item_count.div_ceil(page_size).max(1);
+        let link = |number: usize| base_path.url(&self.page_path(folder, number));
+
+        (1..=page_count)
+            .map(|number| {
+                let path = self.page_path(folder, number);
+
+                Page {
+                    output_path: if path.is_empty() || path.ends_with('/') {
+                        format!("{}index.html", path)
+                    } else {
+                        path
+                    },
+                    range: (number - 1) * page_size..(number * page_size).min(item_count),
+                    pages: (1..=page_count)
+                        .map(|n| json!({"id": n, "current": n == number, "link": link(n)}))
+                        .collect(),
+                    prev: (number > 1).then(|| link(number - 1)),
+                    next: (number < page_count).then(|| link(number + 1)),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(pages: &[Page]) -> Vec<&str> {
+        pages.iter().map(|p| p.output_path.as_str()).collect()
+    }
+
+    #[test]
+    fn empty_listing_has_one_page() {
+        let pages = PaginationOptions::default().paginate("", 0, 5, &BasePath::default());
+
+        assert_eq!(paths(&pages), vec!["index.html"]);
+        assert_eq!(pages[0].range, 0..0);
+        assert_eq!(pages[0].prev, None);
+        assert_eq!(pages[0].next, None);
+    }
+
+    #[test]
+    fn exactly_one_full_page() {
+        let pages = PaginationOptions::default().paginate("tags/rust", 5, 5, &BasePath::default());
+
+        assert_eq!(paths(&pages), vec!["tags/rust/index.html"]);
+        assert_eq!(pages[0].range, 0..5);
+        assert_eq!(pages[0].next, None);
+    }
+
+    #[test]
+    fn one_item_over_a_page() {
+        let pages = PaginationOptions::default().paginate("", 6, 5, &BasePath::new("blog"));
+
+        assert_eq!(paths(&pages), vec!["index.html", "index2.html"]);
+        assert_eq!(pages[0].range, 0..5);
+        assert_eq!(pages[1].range, 5..6);
+        assert_eq!(pages[0].next.as_deref(), Some("/blog/index2.html"));
+        assert_eq!(pages[1].prev.as_deref(), Some("/blog/index.html"));
+        assert_eq!(
+            pages[1].pages,
+            vec![
+                json!({"id": 1, "current": false, "link": "/blog/index.html"}),
+                json!({"id": 2, "current": true, "link": "/blog/index2.html"}),
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_page_path() {
+        let options = PaginationOptions {
+            page_path: String::from("page/{n}/"),
+            ..PaginationOptions::default()
+        };
+        let pages = options.paginate("tags/rust", 11, 5, &BasePath::default());
+
+        assert_eq!(
+            paths(&pages),
+            vec![
+                "tags/rust/index.html",
+                "tags/rust/page/2/index.html",
+                "tags/rust/page/3/index.html",
+            ]
+        );
+        assert_eq!(pages[1].next.as_deref(), Some("/tags/rust/page/3/"));
+        assert_eq!(pages[1].prev.as_deref(), Some("/tags/rust/"));
+        assert_eq!(pages[2].range, 10..11);
+    }
+
+    #[test]
+    fn custom_page_path_on_the_home_page() {
+        let options = PaginationOptions {
+            page_path: String::from("page/{n}/"),
+            ..PaginationOptions::default()
+        };
+        let pages = options.paginate("", 6, 5, &BasePath::new("blog"));
+
+        assert_eq!(paths(&pages), vec!["index.html", "page/2/index.html"]);
+        assert_eq!(pages[1].prev.as_deref(), Some("/blog/"));
+        assert_eq!(pages[0].pages[0]["link"], "/blog/");
+    }
+}
@@ -1,6 +1,14 @@
 
 use serde::ser::{Serialize, SerializeMap, Serializer};
 use chrono::{DateTime, Local,Datelike};
+use std::collections::BTreeMap;
+
+use crate::categories::Category;
+use crate::feed::FeedLink;
+use crate::frontmatter::FrontmatterInfo;
+use crate::md2html::TocEntry;
+use crate::related::RelatedPost;
+use crate::series::Series;
 
 #[derive(Debug, Clone)]
 pub struct Tag {
@@ -25,11 +33,14 @@ impl Serialize for Tag {
 #[derive(Debug, Clone)]
 pub struct Post {
     pub date: DateTime<Local>,
+    pub updated: Option<DateTime<Local>>,
     pub description: String,
+    pub author: Option<String>,
     pub src: String,
     pub md: String,
     pub title: String,
     pub tags: Vec<Tag>,
+    pub categories: Vec<Category>,
     pub word_count: usize,
     pub repo: String,
     pub blog_title: String,
@@ -42,6 +53,32 @@ pub struct Post {
     pub discussion_url: Option<String>,
     pub meta_img: Option<String>,
     pub langs: Vec<String>,
+    pub toc: Vec<TocEntry>,
+    pub highlight_css: bool,
+    pub server_math: bool,
+    pub macros: BTreeMap<String, String>,
+    /// A folder that isn't in `content` yet, only published by `serve`.
+    pub draft: bool,
+    pub unlisted: bool,
+    pub noindex: bool,
+    /// Feeds advertised in the page's head.
+    pub feeds: Vec<FeedLink>,
+    /// Stemmed words of the post and how often they occur. Not serialized,
+    /// they go into the search index and pick related posts.
+    pub terms: BTreeMap<String, usize>,
+    pub related: Vec<RelatedPost>,
+    /// `series` and `series_order` from the frontmatter. Not serialized, they
+    /// are used to build `series`.
+    pub series_title: Option<String>,
+    pub series_order: Option<u32>,
+    pub series: Option<Series>,
+}
+
+impl Post {
+    /// When the post last changed, for the sitemap.
+    pub fn last_modified(&self) -> DateTime<Local> {
+        self.updated.unwrap_or(self.date).max(self.date)
+    }
 }
 
 impl Serialize for Post {
@@ -63,6 +100,20 @@ impl Serialize for Post {
             .as_str(),
         )
         .unwrap();
+        if let Some(updated) = &self.updated {
+            map.serialize_entry(
+                "updated",
+                format!(
+                    "{}-{:0width$}-{:0width$}",
+                    updated.year(),
+                    updated.month(),
+                    updated.day(),
+                    width = 2
+                )
+                .as_str(),
+            )
+            .unwrap();
+        }
         map.serialize_entry("description", &self.description)
             .unwrap();
         map.serialize_entry("src", &self.src).unwrap();
@@ -71,6 +122,10 @@ impl Serialize for Post {
 
         map.serialize_entry("tags", &self.tags).unwrap();
 
+        if !self.categories.is_empty() {
+            map.serialize_entry("categories", &self.categories).unwrap();
+        }
+
         map.serialize_entry("word_count", &self.word_count).unwrap();
         map.serialize_entry("repo", &self.repo).unwrap();
         map.serialize_entry("blog_url", &self.blog_url).unwrap();
@@ -109,12 +164,79 @@ impl Serialize for Post {
             map.serialize_entry("meta_img", mi).unwrap();
         }
 
-        if self.langs.len() > 0 {
+        if !self.langs.is_empty() {
             let mut sorted_langs:Vec<&String> = self.langs.iter().collect();
             sorted_langs.sort();
             map.serialize_entry("langs", &sorted_langs).unwrap();
         }
 
+        if self.highlight_css {
+            map.serialize_entry("highlight_css", &self.highlight_css)
+                .unwrap();
+        }
+
+        if self.server_math {
+            map.serialize_entry("server_math", &self.server_math)
+                .unwrap();
+        }
+
+        if !self.toc.is_empty() {
+            map.serialize_entry("toc", &self.toc).unwrap();
+        }
+
+        if let Some(author) = &self.author {
+            map.serialize_entry("author", author).unwrap();
+        }
+
+        map.serialize_entry("feeds", &self.feeds).unwrap();
+
+        if let Some(series) = &self.series {
+            map.serialize_entry("series", series).unwrap();
+        }
+
+        if !self.related.is_empty() {
+            map.serialize_entry("related", &self.related).unwrap();
+        }
+
+        if self.noindex {
+            map.serialize_entry("noindex", &self.noindex).unwrap();
+        }
+
+        if self.draft {
+            map.serialize_entry("draft", &self.draft).unwrap();
+        }
+
+        // Inlined into a <script>, so a macro can't close the tag early.
+        map.serialize_entry(
+            "macros",
+            &serde_json::to_string(&self.macros)
+                .unwrap()
+                .replace("</", "<\\/"),
+        )
+        .unwrap();
+
         map.end()
     }
 }
+
+/// A post's rendered markdown and what was collected while rendering it. Cached
+/// between publishes, so posts that haven't changed aren't rendered again.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RenderedPost {
+    pub md: String,
+    pub frontmatter: FrontmatterInfo,
+    pub word_count: usize,
+    pub text: String,
+    pub meta_img: Option<String>,
+    pub langs: Vec<String>,
+    pub headings: Vec<TocEntry>,
+    pub macros: BTreeMap<String, String>,
+    pub math_errors: usize,
+    /// Files written next to the post's index.html, relative to the target folder.
+    pub assets: Vec<String>,
+    /// Build cache entries used by the post's images.
+    pub cache_keys: Vec<String>,
+    /// Warnings to print for the post, again when it is reused from the cache.
+    #[serde(default)]
+    pub log: Vec<String>,
+}
@@ -1,27 +1,44 @@
 use chrono::Local;
-use chrono::{DateTime, Datelike};
-use dateparser::parse_with_timezone;
+use chrono::{DateTime, Datelike, NaiveTime};
+use dateparser::parse_with;
 use handlebars::{Handlebars, JsonValue};
 use image::ImageReader;
 use itertools::Itertools;
 use markdown::Options;
-use rss::{ChannelBuilder, GuidBuilder, ImageBuilder, Item, ItemBuilder};
+use rayon::prelude::*;
+use rust_stemmers::Stemmer;
 use serde_json::json;
 use slugify::slugify;
-use std::cmp::{self, Ordering};
-use std::collections::{HashMap, HashSet};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::Path;
 use toml::Value;
 
-use crate::frontmatter::FrontmatterInfo;
-use crate::md2html::{render_markdown, Footnote, SelectedMetaImage};
+use crate::archive::archive;
+use crate::categories::{category_pages, category_tree, use_page_names, Category};
+use crate::base_path::BasePath;
+use crate::cache::{BuildCache, CACHE_FOLDER};
+use crate::callout::{builtin_callout_types, CalloutType};
+use crate::embed::EmbedRegistry;
+use crate::feed::{atom_feed, json_feed, rss_channel, FeedInfo, FeedLink, FeedOptions};
+use crate::highlight::Highlighter;
+use crate::images::ImageOptions;
+use crate::math::MathMode;
+use crate::md2html::{build_toc, render_markdown, RenderContext, RenderOptions, SelectedMetaImage};
 use crate::new::populate_templates;
-use crate::post::{Post, Tag};
+use crate::pagination::PaginationOptions;
+use crate::manifest::{folder_hash, inputs_hash, BuildManifest};
+use crate::post::{Post, RenderedPost, Tag};
+use crate::related::{RelatedIndex, RelatedOptions};
+use crate::series::find_series;
+use crate::tags::{tag_index, TagRegistry};
+use crate::search::{search_index, stemmer_algorithm, terms, SearchOptions};
+use crate::sitemap::{RobotsOptions, Sitemap};
 use markdown::to_mdast;
 
 pub fn generate_google_analytics_id(id: &str) -> String {
-    return format!(
+    format!(
         "<!-- Google tag (gtag.js) -->\n\
     <script async src=\"https://www.googletagmanager.com/gtag/js?id={}\"></script>\n\
     <script>\n\
@@ -31,64 +48,166 @@ pub fn generate_google_analytics_id(id: &str) -> String {
       gtag('config', '{}');\n\
     </script>",
         id, id
-    );
+    )
 }
 
-pub fn publish(target: String, force_overwrite_theme: bool) {
+/// Renders a post's markdown. Images and other files the post uses are written
+/// to its folder in the target.
+fn render_post(
+    folder: &str,
+    target_folder: &str,
+    render_options: &RenderOptions,
+    logo: Option<SelectedMetaImage>,
+) -> RenderedPost {
+    let target_folder_exists = Path::new(format!("{}/{}", target_folder, folder).as_str()).exists();
+
+    if !target_folder_exists {
+        fs::create_dir(format!("{}/{}", target_folder, folder).as_str())
+            .unwrap_or_else(|_| panic!("Unable to create target folder: {}.", &folder));
+    }
+
+    let path = format!("{}/content.md", folder);
+
+    let markdown = fs::read_to_string(path).expect("Should have been able to read the file");
+
+    //println!("markdown {}", markdown);
+
+    let mut options = Options::gfm();
+    options.parse.constructs.math_text = true;
+    options.parse.constructs.frontmatter = true;
+    options.parse.constructs.math_flow = true;
+
+    let ast = to_mdast(&markdown, &options.parse).unwrap();
+
+    //println!("{:?}", ast);
+    let mut rendered_string = String::new();
+    let mut ctx = RenderContext::new(render_options, folder, target_folder, logo);
+
+    render_markdown(&ast, &mut rendered_string, &mut ctx);
+
+    let RenderContext {
+        word_count,
+        text,
+        frontmatter,
+        langs,
+        selected_meta_image,
+        footnotes,
+        headings,
+        math_errors,
+        macros,
+        cache_keys,
+        log,
+        ..
+    } = ctx;
+
+    if !footnotes.is_empty() {
+        rendered_string += "<table class=\"footnote-def\">";
+        for key in footnotes.keys().sorted() {
+            let f = footnotes.get(key).unwrap();
+            rendered_string += format!(
+                "<tr class=\"footnote-row\" id=\"footnote_{}\"><td>[{}]: </td><td>{}</td></tr>",
+                f.id, f.count, f.html
+            )
+            .as_str();
+        }
+        rendered_string += "</table>";
+    }
+
+    let mut langs_sorted = Vec::from_iter(langs);
+    langs_sorted.sort();
+
+    RenderedPost {
+        md: rendered_string,
+        frontmatter,
+        word_count,
+        text,
+        meta_img: selected_meta_image.map(|si| si.url),
+        langs: langs_sorted,
+        headings,
+        macros,
+        math_errors,
+        assets: Vec::new(),
+        cache_keys,
+        log,
+    }
+}
+
+/// How a blog is published, by `pub` or by `serve`.
+pub struct PublishOptions {
+    pub target: String,
+    pub force_overwrite_theme: bool,
+    pub no_cache: bool,
+    pub jobs: Option<usize>,
+    /// `--prefix`, overrides `prefix` in quipquick.toml.
+    pub prefix: Option<String>,
+    /// Set by `serve`: `target` is used even when quipquick.toml has one, and
+    /// drafts are published too.
+    pub preview: bool,
+}
+
+/// Folders that can't hold a post: the theme, and the folders the blog's own
+/// pages are generated into.
+pub fn reserved_folders(search_enabled: bool, gallery: Option<&str>) -> Vec<&str> {
+    let mut reserved = vec!["template", "tags", "series", "categories", "archive"];
+    if search_enabled {
+        reserved.push("search");
+    }
+    reserved.extend(gallery);
+    reserved
+}
+
+/// Folders with a content.md that aren't in `content` yet, or `reserved`.
+pub fn find_drafts(folders: &[String], reserved: &[&str]) -> Vec<String> {
+    let mut drafts: Vec<String> = fs::read_dir(".")
+        .unwrap()
+        .flatten()
+        .filter(|entry| entry.path().join("content.md").is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|folder| {
+            !folder.starts_with('.')
+                && !reserved.contains(&folder.as_str())
+                && !folders.contains(folder)
+        })
+        .collect();
+    drafts.sort();
+    drafts
+}
+
+pub fn publish(options: &PublishOptions) -> Result<(), String> {
     let current_time: DateTime<Local> = Local::now();
 
-    populate_templates("./", force_overwrite_theme);
+    populate_templates("./", options.force_overwrite_theme);
 
     const VERSION: &str = env!("CARGO_PKG_VERSION");
 
     let contents =
         fs::read_to_string("quipquick.toml").expect("Should have been able to read the file");
 
-    let value = match contents.parse::<Value>() {
+    let value = match toml::from_str::<Value>(&contents) {
         Err(error) => {
-            println!("Toml Parsing Error: {}", error.to_string());
-            return;
+            return Err(format!("Toml Parsing Error: {}", error));
         }
         Ok(value) => value,
     };
 
     if let toml::Value::Table(ref global) = value {
-        let target_folder = global
-            .get("target")
-            .and_then(|value| value.as_str())
-            .unwrap_or(&target)
-            .to_owned();
-
-        let target_folder_exists = Path::new(&target_folder).exists();
+        let target_folder = if options.preview {
+            options.target.clone()
+        } else {
+            global
+                .get("target")
+                .and_then(|value| value.as_str())
+                .unwrap_or(&options.target)
+                .to_owned()
+        };
 
-        if target_folder_exists {
-            let target_is_dir: bool = Path::new(&target_folder).is_dir();
-            if !target_is_dir {
-                println!("Target {} is not a folder.", &target_folder);
-                return;
-            }
-
-            let items = fs::read_dir(&target_folder).unwrap();
-
-            for path in items {
-                if let Ok(item) = path {
-                    if !item.file_name().eq_ignore_ascii_case(".git")
-                        && !item.file_name().eq_ignore_ascii_case("README.md")
-                    {
-                        println!("Removing {:?} {:?}", item.path(), item.file_name());
-                        if let Ok(file_type) = item.file_type() {
-                            if file_type.is_dir() {
-                                fs::remove_dir_all(item.path()).unwrap();
-                            } else {
-                                fs::remove_file(item.path()).unwrap();
-                            }
-                        }
-                    }
-                }
+        if Path::new(&target_folder).exists() {
+            if !Path::new(&target_folder).is_dir() {
+                return Err(format!("Target {} is not a folder.", &target_folder));
             }
         } else {
             fs::create_dir(&target_folder)
-                .expect(format!("Unable to create target folder: {}.", &target_folder).as_str());
+                .unwrap_or_else(|_| panic!("Unable to create target folder: {}.", &target_folder));
         }
 
         let blog_title = global
@@ -109,11 +228,26 @@ pub fn publish(target: String, force_overwrite_theme: bool) {
             .unwrap_or("")
             .to_owned();
 
-        let blog_url = global
-            .get("url")
-            .and_then(|value| value.as_str())
-            .expect("Blog url is mandatory!")
-            .to_owned();
+        // A preview is served from the root.
+        let base_path = if options.preview {
+            BasePath::default()
+        } else {
+            BasePath::new(
+                options.prefix.as_deref().unwrap_or(
+                    global
+                        .get("prefix")
+                        .and_then(|value| value.as_str())
+                        .unwrap_or(""),
+                ),
+            )
+        };
+
+        let blog_url = base_path.blog_url(
+            global
+                .get("url")
+                .and_then(|value| value.as_str())
+                .expect("Blog url is mandatory!"),
+        );
 
         let google_analytics_id = global
             .get("google_analytics_id")
@@ -121,13 +255,11 @@ pub fn publish(target: String, force_overwrite_theme: bool) {
             .unwrap_or("")
             .to_owned();
 
-        let discussion_url = global.get("discussion_url").and_then(|value| {
-            Some(
-                value
-                    .as_str()
-                    .expect("Discussion url has to be a string")
-                    .to_owned(),
-            )
+        let discussion_url = global.get("discussion_url").map(|value| {
+            value
+                .as_str()
+                .expect("Discussion url has to be a string")
+                .to_owned()
         });
 
         let logo = global.get("logo").and_then(|value| {
@@ -156,101 +288,390 @@ pub fn publish(target: String, force_overwrite_theme: bool) {
 
         let gallery = global.get("gallery").and_then(|value| value.as_str());
 
+        let highlight = global.get("highlight").and_then(|value| value.as_table());
+
+        let highlighter = if highlight
+            .and_then(|h| h.get("mode"))
+            .and_then(|value| value.as_str())
+            .unwrap_or("client")
+            == "build"
+        {
+            let theme = highlight
+                .and_then(|h| h.get("theme"))
+                .and_then(|value| value.as_str())
+                .unwrap_or("base16-ocean.dark");
+
+            match Highlighter::new(theme) {
+                Ok(h) => Some(h),
+                Err(error) => {
+                    return Err(format!("Highlight Error: {}", error));
+                }
+            }
+        } else {
+            None
+        };
+
+        let math_mode = match global
+            .get("math")
+            .and_then(|value| value.as_table())
+            .and_then(|m| m.get("mode"))
+            .and_then(|value| value.as_str())
+            .unwrap_or("client")
+        {
+            "client" => MathMode::Client,
+            "mathml" => MathMode::MathMl,
+            other => {
+                return Err(format!(
+                    "Unknown math mode {}, expected \"client\" or \"mathml\".",
+                    other
+                ));
+            }
+        };
+
+        let mut math_macros = BTreeMap::new();
+        if let Some(macros) = global
+            .get("math")
+            .and_then(|value| value.as_table())
+            .and_then(|m| m.get("macros"))
+            .and_then(|value| value.as_table())
+        {
+            for (name, body) in macros {
+                match body.as_str() {
+                    Some(body) => {
+                        math_macros.insert(name.clone(), body.to_string());
+                    }
+                    None => {
+                        return Err(format!("Math macro {} should be a string.", name));
+                    }
+                }
+            }
+        }
+
+        let mut callout_types = builtin_callout_types();
+        if let Some(callouts) = global.get("callouts").and_then(|value| value.as_table()) {
+            for (name, callout) in callouts {
+                let Some(callout) = callout.as_table() else {
+                    return Err(format!(
+                        "Callout {} should be a table, e.g. [callouts.{}].",
+                        name, name
+                    ));
+                };
+
+                callout_types.insert(
+                    name.to_lowercase(),
+                    CalloutType {
+                        title: callout
+                            .get("title")
+                            .and_then(|value| value.as_str())
+                            .map(|title| title.to_string())
+                            .unwrap_or_else(|| titlecase::titlecase(name)),
+                        collapsible: callout
+                            .get("collapsible")
+                            .and_then(|value| value.as_bool())
+                            .unwrap_or(false),
+                    },
+                );
+            }
+        }
+
+        let images = ImageOptions::from_config(&value)?;
+
+        let pagination = PaginationOptions::from_config(&value)?;
+
+        let robots = RobotsOptions::from_config(&value)?;
+
+        let feed_options = FeedOptions::from_config(&value)?;
+
+        let author = global
+            .get("author")
+            .and_then(|value| value.as_str())
+            .map(|author| author.to_string());
+
+        let feeds = global.get("feeds").and_then(|value| value.as_table());
+        let atom = feeds
+            .and_then(|f| f.get("atom"))
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false);
+        let json_feed_enabled = feeds
+            .and_then(|f| f.get("json"))
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false);
+
+        let mut feed_links = vec![FeedLink {
+            kind: "application/rss+xml".to_string(),
+            title: blog_title.clone(),
+            href: format!("{}/rss.xml", blog_url),
+        }];
+        if atom {
+            feed_links.push(FeedLink {
+                kind: "application/atom+xml".to_string(),
+                title: blog_title.clone(),
+                href: format!("{}/atom.xml", blog_url),
+            });
+        }
+        if json_feed_enabled {
+            feed_links.push(FeedLink {
+                kind: "application/feed+json".to_string(),
+                title: blog_title.clone(),
+                href: format!("{}/feed.json", blog_url),
+            });
+        }
+
+        let search = SearchOptions::from_config(&value)?;
+
+        let tag_registry = TagRegistry::from_config(&value)?;
+
+        let tag_cloud = global
+            .get("tag_index")
+            .and_then(|value| value.get("cloud"))
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false);
+
+        let related = RelatedOptions::from_config(&value)?;
+
+        let stemmer = Stemmer::create(stemmer_algorithm(&search.language).unwrap());
+
+        let render_options = RenderOptions {
+            heading_permalinks: global
+                .get("heading_permalinks")
+                .and_then(|value| value.as_bool())
+                .unwrap_or(true),
+            highlighter,
+            math_mode,
+            math_macros,
+            callout_types,
+            embeds: EmbedRegistry::new("template"),
+            images,
+            cache: BuildCache::new(CACHE_FOLDER, !options.no_cache),
+        };
+
+        let mut manifest = BuildManifest::load(&target_folder, &render_options.cache);
+
+        if manifest.is_first_build() {
+            let items = fs::read_dir(&target_folder).unwrap();
+
+            for item in items.flatten() {
+                if !item.file_name().eq_ignore_ascii_case(".git")
+                    && !item.file_name().eq_ignore_ascii_case("README.md")
+                {
+                    println!("Removing {:?} {:?}", item.path(), item.file_name());
+                    if let Ok(file_type) = item.file_type() {
+                        if file_type.is_dir() {
+                            fs::remove_dir_all(item.path()).unwrap();
+                        } else {
+                            fs::remove_file(item.path()).unwrap();
+                        }
+                    }
+                }
+            }
+        }
+
+        // Everything a post's rendering depends on besides its own folder. The
+        // content list is left out so adding a post doesn't re-render the others.
+        let mut config = global.clone();
+        config.remove("content");
+        let render_inputs = inputs_hash(&[
+            VERSION,
+            &toml::to_string(&config).unwrap(),
+            &folder_hash("template/embeds"),
+        ]);
+
         let template = fs::read_to_string("template/post.html")
             .expect("Should have been able to read the file");
 
-        let reg = Handlebars::new();
-        // reg.register_helper("md", Box::new(md));
+        let mut reg = Handlebars::new();
+        reg.register_helper("url", Box::new(base_path.clone()));
+
+        // The head, header and footer every page shares. `{{> head page_title="Tags"}}`
+        // sets the page's title, and pages can override the head's `meta` and
+        // add to its `scripts` with inline partials.
+        let mut partials = String::new();
+        for name in ["head", "header", "footer"] {
+            let partial = fs::read_to_string(format!("template/partials/{}.html", name))
+                .expect("Should have been able to read the file");
+            reg.register_partial(name, &partial).unwrap();
+            partials += &partial;
+        }
 
         let mut post_list: Vec<Post> = Vec::new();
+        let mut math_errors: usize = 0;
 
-        for c in content {
-            let folder = c.as_str().unwrap();
+        let mut folders: Vec<String> = content
+            .iter()
+            .map(|c| c.as_str().unwrap().to_string())
+            .collect();
 
-            if folder == "tags" {
-                println!("There shouldn't be a content folder named tags");
-                return;
-            }
+        let reserved = reserved_folders(search.enabled, gallery);
+        if let Some(folder) = folders.iter().find(|f| reserved.contains(&f.as_str())) {
+            return Err(format!("There shouldn't be a content folder named {}", folder));
+        }
 
-            let target_folder_exists =
-                Path::new(format!("{}/{}", target_folder, folder).as_str()).exists();
+        let drafts = if options.preview {
+            find_drafts(&folders, &reserved)
+        } else {
+            Vec::new()
+        };
+        folders.extend(drafts.iter().cloned());
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(options.jobs.unwrap_or(0))
+            .build()
+            .unwrap();
+
+        let cached_posts: Vec<(String, Option<RenderedPost>)> = pool.install(|| {
+            folders
+                .par_iter()
+                .map(|folder| {
+                    let post_inputs = inputs_hash(&[&render_inputs, &folder_hash(folder)]);
+
+                    let cached = render_options
+                        .cache
+                        .get_string(&format!("{}.post.json", post_inputs))
+                        .and_then(|p| serde_json::from_str::<RenderedPost>(&p).ok())
+                        .filter(|p| p.assets.iter().all(|a| manifest.is_fresh(a, &post_inputs)));
+
+                    (post_inputs, cached)
+                })
+                .collect()
+        });
 
-            if !target_folder_exists {
-                fs::create_dir(format!("{}/{}", target_folder, folder).as_str())
-                    .expect(format!("Unable to create target folder: {}.", &folder).as_str());
+        // Content folders can be nested, e.g. `a` and `a/b`. A post's files
+        // are those under its folder but not under a nested post's.
+        let nested: Vec<Vec<&str>> = folders
+            .iter()
+            .map(|folder| {
+                let prefix = format!("{}/", folder);
+                folders
+                    .iter()
+                    .filter(|f| f.starts_with(&prefix))
+                    .map(|f| f.as_str())
+                    .collect()
+            })
+            .collect();
+
+        // Files from the previous publish are removed before any post is
+        // rendered, so a folder nested in another post's folder isn't emptied
+        // while it's being written.
+        for ((folder, nested), (_, cached)) in folders.iter().zip(&nested).zip(&cached_posts) {
+            if cached.is_none() {
+                manifest.remove_previous(folder, nested);
             }
+        }
 
-            let path = format!("{}/content.md", folder);
-
-            let markdown =
-                fs::read_to_string(path).expect("Should have been able to read the file");
+        // Posts are independent until they are sorted and linked, so they are
+        // rendered in parallel. Results and logs are then handled in content order,
+        // which keeps the output deterministic.
+        let rendered_posts: Vec<(String, RenderedPost)> = pool.install(|| {
+            folders
+                .par_iter()
+                .zip(&nested)
+                .zip(cached_posts)
+                .map(|((folder, nested), (post_inputs, cached))| {
+                    if let Some(rendered) = cached {
+                        render_options.cache.keep(&rendered.cache_keys);
+                        return (post_inputs, rendered);
+                    }
 
-            //println!("markdown {}", markdown);
+                    let mut rendered =
+                        render_post(folder, &target_folder, &render_options, logo.clone());
+                    let page = format!("{}/index.html", folder);
+                    rendered.assets = manifest
+                        .post_files(folder, nested)
+                        .into_iter()
+                        .filter(|f| *f != page)
+                        .collect();
+
+                    // Posts with errors are rendered every time, so the errors are
+                    // reported every time.
+                    if rendered.math_errors == 0 {
+                        render_options.cache.put_string(
+                            &format!("{}.post.json", post_inputs),
+                            &serde_json::to_string(&rendered).unwrap(),
+                        );
+                    }
 
-            let mut options = Options::gfm();
-            options.parse.constructs.math_text = true;
-            options.parse.constructs.frontmatter = true;
-            options.parse.constructs.math_flow = true;
+                    (post_inputs, rendered)
+                })
+                .collect()
+        });
 
-            let ast = to_mdast(&markdown, &options.parse).unwrap();
+        for (folder, (post_inputs, rendered)) in folders.iter().zip(rendered_posts) {
+            for message in &rendered.log {
+                println!("{}", message);
+            }
 
-            //println!("{:?}", ast);
-            let mut rendered_string = String::new();
-            let mut word_count: usize = 0;
-            let mut frontmatter: FrontmatterInfo = FrontmatterInfo {
-                title: String::new(),
-                date: String::new(),
-                description: String::new(),
-                tags: vec![],
-            };
-            let mut selected_meta_image = logo.clone();
-            let mut langs: HashSet<String> = HashSet::new();
-            let mut footnotes: HashMap<String, Footnote> = HashMap::new();
-
-            render_markdown(
-                &ast,
-                &mut rendered_string,
-                folder,
-                &target_folder,
-                &mut word_count,
-                &mut frontmatter,
-                &mut langs,
-                &mut selected_meta_image,
-                &mut footnotes,
-            );
-            if footnotes.len() > 0 {
-                rendered_string += "<table class=\"footnote-def\">";
-                for key in footnotes.keys().sorted() {
-                    let f = footnotes.get(key).unwrap();
-                    rendered_string += format!(
-                            "<tr class=\"footnote-row\" id=\"footnote_{}\"><td>[{}]: </td><td>{}</td></tr>",
-                            f.id, f.count, f.html
-                        )
-                        .as_str();
-                }
-                rendered_string += "</table>";
+            for asset in &rendered.assets {
+                manifest.record(asset, &post_inputs);
             }
 
-            let d = parse_with_timezone(&frontmatter.date, &chrono::offset::Local).unwrap();
+            let RenderedPost {
+                md,
+                frontmatter,
+                word_count,
+                text,
+                meta_img,
+                langs,
+                headings,
+                macros,
+                math_errors: post_math_errors,
+                ..
+            } = rendered;
+
+            math_errors += post_math_errors;
+
+            // Dates without a time are midnight, not the time of the publish, so
+            // feed dates don't change from one publish to the next.
+            let d = parse_with(&frontmatter.date, &chrono::offset::Local, NaiveTime::MIN).unwrap();
+            let updated = frontmatter.updated.as_ref().map(|updated| {
+                parse_with(updated, &chrono::offset::Local, NaiveTime::MIN)
+                    .unwrap_or_else(|_| panic!("Invalid updated date {} in {}.", updated, folder))
+                    .into()
+            });
+            // Aliases can leave a post with the same tag twice.
             let mut tags: Vec<Tag> = Vec::new();
             for t in &frontmatter.tags {
-                tags.push(Tag {
-                    slug: slugify!(t),
-                    tag: t.to_lowercase(),
-                });
+                let tag = tag_registry.tag(t);
+                if !tags.iter().any(|other| other.slug == tag.slug) {
+                    tags.push(tag);
+                }
             }
 
-            let mut langs_sorted = Vec::from_iter(langs);
-            langs_sorted.sort();
+            let mut categories: Vec<Category> = Vec::new();
+            for c in &frontmatter.categories {
+                match Category::parse(c, &base_path) {
+                    Ok(category) => {
+                        if !categories.iter().any(|other| other.path == category.path) {
+                            categories.push(category);
+                        }
+                    }
+                    Err(error) => println!("{}: {} It's left out.", folder, error),
+                }
+            }
+
+            if let Some(series) = &frontmatter.series
+                && slugify!(series).is_empty()
+            {
+                return Err(format!(
+                    "{} is in series {}, which has no letters or digits for its URL.",
+                    folder, series
+                ));
+            }
+
+            if frontmatter.series.is_none() && frontmatter.series_order.is_some() {
+                println!("{} has a series_order, but isn't part of a series.", folder);
+            }
 
             let data = Post {
                 date: d.into(),
+                updated,
                 description: frontmatter.description,
+                author: frontmatter.author.or_else(|| author.clone()),
                 src: folder.to_string(),
-                md: rendered_string,
+                md,
                 title: titlecase::titlecase(&frontmatter.title),
-                tags: tags,
-                word_count: word_count,
+                tags,
+                categories,
+                word_count,
                 blog_title: blog_title.clone(),
                 blog_url: blog_url.clone(),
                 repo: repo.clone(),
@@ -260,33 +681,134 @@ pub fn publish(target: String, force_overwrite_theme: bool) {
                 older_post: None,
                 newer_post: None,
                 discussion_url: discussion_url.clone(),
-                meta_img: if let Some(si) = selected_meta_image {
-                    Some(si.url)
+                meta_img,
+                langs,
+                toc: if frontmatter.toc {
+                    build_toc(headings)
                 } else {
-                    None
+                    Vec::new()
                 },
-                langs: langs_sorted,
+                highlight_css: render_options.highlighter.is_some(),
+                server_math: render_options.math_mode == MathMode::MathMl,
+                macros,
+                draft: drafts.contains(folder),
+                unlisted: frontmatter.unlisted,
+                noindex: frontmatter.noindex,
+                feeds: feed_links.clone(),
+                terms: terms(&text, &stemmer),
+                related: Vec::new(),
+                series_title: frontmatter.series,
+                series_order: frontmatter.series_order,
+                series: None,
             };
             post_list.push(data.clone());
         }
 
         post_list.sort_by(|a, b| {
             if a.date < b.date {
-                return Ordering::Greater;
+                Ordering::Greater
             } else if a.date == b.date {
-                return Ordering::Equal;
+                Ordering::Equal
             } else {
-                return Ordering::Less;
+                Ordering::Less
             }
         });
 
-        const PAGE_ITEM_COUNT: u32 = 5;
+        // Unlisted posts get a page, but other pages don't link to them.
+        let (mut unlisted_posts, mut post_list): (Vec<Post>, Vec<Post>) =
+            post_list.into_iter().partition(|post| post.unlisted);
+
+        // Related posts are picked among listed posts only, but unlisted posts
+        // link to them too.
+        let (related_posts, unlisted_related_posts): (Vec<_>, Vec<_>) = {
+            let related_index = RelatedIndex::new(&post_list);
+            let find = |post: &Post| related_index.related(post, &related);
+            (
+                post_list.iter().map(find).collect(),
+                unlisted_posts.iter().map(find).collect(),
+            )
+        };
+        for (post, related_posts) in post_list.iter_mut().zip(related_posts) {
+            post.related = related_posts;
+        }
+        for (post, related_posts) in unlisted_posts.iter_mut().zip(unlisted_related_posts) {
+            post.related = related_posts;
+        }
 
-        let page_size: u32 = (post_list.len() as f32 / PAGE_ITEM_COUNT as f32).ceil() as u32;
+        let mut series_log = Vec::new();
+        let series = find_series(&post_list, &mut series_log);
+        for message in &series_log {
+            println!("{}", message);
+        }
+        let find_navigation = |post: &Post| {
+            post.series_title.as_ref().and_then(|title| {
+                let slug = slugify!(title);
+                series
+                    .iter()
+                    .find(|s| s.slug == slug)
+                    .map(|s| s.navigation(&post_list, &post.src))
+            })
+        };
+        let navigation: Vec<_> = post_list.iter().map(find_navigation).collect();
+        let unlisted_navigation: Vec<_> = unlisted_posts.iter().map(find_navigation).collect();
+        for (post, navigation) in post_list.iter_mut().zip(navigation) {
+            post.series = navigation;
+        }
+        for (post, navigation) in unlisted_posts.iter_mut().zip(unlisted_navigation) {
+            post.series = navigation;
+        }
 
-        let mut tags: HashMap<String, (String, Vec<u32>)> = HashMap::new();
+        let categories = category_pages(&post_list);
+        for post in post_list.iter_mut().chain(unlisted_posts.iter_mut()) {
+            for category in post.categories.iter_mut() {
+                use_page_names(category, &categories);
+            }
+        }
+
+        let mut sitemap = Sitemap::new(&blog_url);
+
+        let mut write_post_page = |post: &Post| {
+            let page = format!("{}/index.html", &post.src);
+            // The header's data besides what the post has.
+            let mut data = serde_json::to_value(post).unwrap();
+            let map = data.as_object_mut().unwrap();
+            map.insert("blog_description".to_string(), json!(blog_description));
+            map.insert("gallery".to_string(), json!(gallery));
+            map.insert("search".to_string(), json!(search.enabled));
+            map.insert("category_index".to_string(), json!(!categories.is_empty()));
+
+            let page_inputs = inputs_hash(&[
+                &data.to_string(),
+                &template,
+                &partials,
+                base_path.prefix(),
+            ]);
+
+            if !manifest.is_fresh(&page, &page_inputs) {
+                println!(
+                    "Generating article {} {}",
+                    format!(
+                        "{}-{:0width$}-{:0width$}",
+                        &post.date.year(),
+                        &post.date.month(),
+                        &post.date.day(),
+                        width = 2
+                    )
+                    .as_str(),
+                    &post.title
+                );
+            }
+
+            manifest.write(&page, &page_inputs, || {
+                reg.render_template(&template, &data).unwrap()
+            });
+
+            if !post.unlisted && !post.noindex {
+                sitemap.add(&page, Some(post.last_modified()));
+            }
+        };
 
-        let mut rss_items: Vec<Item> = Vec::new();
+        let mut tags: HashMap<String, (String, Vec<u32>)> = HashMap::new();
 
         for index in 0..post_list.len() {
             if index > 0 {
@@ -303,24 +825,7 @@ pub fn publish(target: String, force_overwrite_theme: bool) {
                 ));
             }
 
-            println!(
-                "Generating article {} {}",
-                format!(
-                    "{}-{:0width$}-{:0width$}",
-                    &post_list[index].date.year(),
-                    &post_list[index].date.month(),
-                    &post_list[index].date.day(),
-                    width = 2
-                )
-                .as_str(),
-                &post_list[index].title
-            );
-
-            let rendered = reg.render_template(&template, &post_list[index]).unwrap();
-
-            let output_path = format!("{}/{}/index.html", target_folder, &post_list[index].src);
-
-            fs::write(output_path, rendered).unwrap();
+            write_post_page(&post_list[index]);
 
             for t in &post_list[index].tags {
                 if tags.contains_key(&t.slug) {
@@ -329,210 +834,456 @@ pub fn publish(target: String, force_overwrite_theme: bool) {
                     tags.insert(t.slug.clone(), (t.tag.clone(), vec![index as u32]));
                 }
             }
+        }
 
-            let permanent_link = format!("{}/{}", &blog_url, post_list[index].src);
-            let guid = GuidBuilder::default().value(permanent_link.clone()).build();
-
-            let item = ItemBuilder::default()
-                .title(Some(titlecase::titlecase(&post_list[index].title)))
-                .link(Some(permanent_link))
-                .description(Some(post_list[index].description.clone()))
-                .comments(post_list[index].discussion_url.clone())
-                .guid(Some(guid))
-                .build();
-            rss_items.push(item)
-        }
-
-        let rss_output_path = format!("{}/rss.xml", target_folder);
-
-        let channel = if let Some(l) = &logo {
-            let rss_image = ImageBuilder::default()
-                .url(format!("{}/{}", &blog_url, l.url))
-                .title(blog_title.clone())
-                .link(blog_url.clone())
-                .build();
-
-            let channel = ChannelBuilder::default()
-                .title(blog_title.clone())
-                .link(blog_url.clone())
-                .description(blog_description.clone())
-                .items(rss_items)
-                .image(Some(rss_image))
-                .build();
-
-            channel
-        } else {
-            let channel = ChannelBuilder::default()
-                .title(blog_title.clone())
-                .link(blog_url.clone())
-                .description(blog_description.clone())
-                .items(rss_items)
-                .build();
-
-            channel
+        for post in &unlisted_posts {
+            write_post_page(post);
+        }
+
+        let channel = rss_channel(
+            FeedInfo {
+                title: blog_title.clone(),
+                link: blog_url.clone(),
+                description: blog_description.clone(),
+            },
+            &post_list,
+            &blog_url,
+            logo.as_ref(),
+            &feed_options,
+        );
+        manifest.write_content("rss.xml", channel.to_string());
+
+        let blog_feed_info = || FeedInfo {
+            title: blog_title.clone(),
+            link: blog_url.clone(),
+            description: blog_description.clone(),
         };
-        fs::write(rss_output_path, channel.to_string()).unwrap();
+
+        if atom {
+            let feed = atom_feed(
+                blog_feed_info(),
+                &format!("{}/atom.xml", blog_url),
+                &post_list,
+                &blog_url,
+                author.as_deref(),
+                &feed_options,
+                current_time,
+            );
+            manifest.write_content("atom.xml", feed.to_string());
+        }
+
+        if json_feed_enabled {
+            let feed = json_feed(
+                blog_feed_info(),
+                &format!("{}/feed.json", blog_url),
+                &post_list,
+                &blog_url,
+                author.as_deref(),
+                &feed_options,
+            );
+            manifest.write_content("feed.json", feed);
+        }
         let index_template = fs::read_to_string("template/index.html")
             .expect("Should have been able to read the file");
 
-        for index in 0..page_size {
-            let mut pages = Vec::new();
+        if let Some(logo) = &logo {
+            manifest.copy(&logo.url, &logo.url);
+        }
+
+        // The home page and tag pages are listings of posts that share the index
+        // template. They differ in their folder, page size and `extra` data.
+        let write_listing = |manifest: &mut BuildManifest,
+                             sitemap: &mut Sitemap,
+                             folder: &str,
+                             posts: &[Post],
+                             page_size: usize,
+                             extra: &[(&str, JsonValue)]| {
+            for page in pagination.paginate(folder, posts.len(), page_size, &base_path) {
+                let mut data = json!({
+                    "posts": posts[page.range],
+                    "repo": repo,
+                    "pages": page.pages,
+                    "blog_title": blog_title,
+                    "blog_description": blog_description,
+                    "blog_url":blog_url,
+                    "quipquick_version": VERSION,
+                    "google_analytics": generate_google_analytics_id(&google_analytics_id),
+                    "gallery": gallery,
+                    "feeds": feed_links,
+                    "search": search.enabled,
+                    "category_index": !categories.is_empty(),
+                });
+
+                let map = data.as_object_mut().unwrap();
+
+                if let Some(logo) = &logo {
+                    map.insert("logo".to_string(), JsonValue::String(logo.url.clone()));
+                }
+
+                if let Some(prev) = page.prev {
+                    map.insert("prev".to_string(), JsonValue::String(prev));
+                }
+
+                if let Some(next) = page.next {
+                    map.insert("next".to_string(), JsonValue::String(next));
+                }
 
-            for pindex in 0..page_size {
-                pages.push(json!({"id":pindex+1,"current":index == pindex, "link":if pindex ==0 {String::from("/index.html")} else {format!("/index{}.html",pindex+1)}}));
+                for (key, value) in extra {
+                    map.insert(key.to_string(), value.clone());
+                }
+
+                let inputs = inputs_hash(&[
+                    &data.to_string(),
+                    &index_template,
+                    &partials,
+                    base_path.prefix(),
+                ]);
+                manifest.write(&page.output_path, &inputs, || {
+                    reg.render_template(&index_template, &data).unwrap()
+                });
+
+                sitemap.add(
+                    &page.output_path,
+                    posts.iter().map(|post| post.last_modified()).max(),
+                );
             }
+        };
 
-            let page_range = (index * PAGE_ITEM_COUNT) as usize
-                ..cmp::min((index + 1) * PAGE_ITEM_COUNT, post_list.len() as u32) as usize;
+        write_listing(
+            &mut manifest,
+            &mut sitemap,
+            "",
+            &post_list,
+            pagination.home_page_size,
+            &[],
+        );
+
+        let tag_counts: Vec<(String, String, usize)> = tags
+            .iter()
+            .map(|(slug, (tag, indices))| (slug.clone(), tag.clone(), indices.len()))
+            .collect();
+
+        for (slug, (tag, indices)) in tags {
+            let tag_post_list: Vec<Post> = indices
+                .iter()
+                .map(|index| post_list[*index as usize].clone())
+                .collect();
+            let description = tag_registry.description(&slug);
+
+            let tag_feed = rss_channel(
+                FeedInfo {
+                    title: format!("{}: #{}", blog_title, tag),
+                    link: format!("{}/tags/{}/", blog_url, slug),
+                    description: description.unwrap_or(&blog_description).to_string(),
+                },
+                &tag_post_list,
+                &blog_url,
+                logo.as_ref(),
+                &feed_options,
+            );
+            manifest.write_content(&format!("tags/{}/rss.xml", slug), tag_feed.to_string());
+
+            write_listing(
+                &mut manifest,
+                &mut sitemap,
+                &format!("tags/{}", slug),
+                &tag_post_list,
+                pagination.tag_page_size,
+                &[
+                    ("page_tag", JsonValue::String(tag)),
+                    ("page_tag_description", json!(description)),
+                    (
+                        "tag_feed",
+                        JsonValue::String(format!("{}/tags/{}/rss.xml", blog_url, slug)),
+                    ),
+                ],
+            );
+        }
+
+        let tags_template = fs::read_to_string("template/tags.html")
+            .expect("Should have been able to read the file");
+
+        let mut data = json!({
+            "tags": tag_index(&tag_counts, &tag_registry, &base_path),
+            "count": tag_counts.len(),
+            "cloud": tag_cloud,
+            "repo": repo,
+            "blog_title": blog_title,
+            "blog_description": blog_description,
+            "blog_url": blog_url,
+            "quipquick_version": VERSION,
+            "google_analytics": generate_google_analytics_id(&google_analytics_id),
+            "gallery": gallery,
+            "feeds": feed_links,
+            "search": search.enabled,
+            "category_index": !categories.is_empty(),
+        });
+
+        if let Some(logo) = &logo {
+            data.as_object_mut()
+                .unwrap()
+                .insert("logo".to_string(), JsonValue::String(logo.url.clone()));
+        }
+
+        let inputs = inputs_hash(&[
+            &data.to_string(),
+            &tags_template,
+            &partials,
+            base_path.prefix(),
+        ]);
+        manifest.write("tags/index.html", &inputs, || {
+            reg.render_template(&tags_template, &data).unwrap()
+        });
+        sitemap.add(
+            "tags/index.html",
+            post_list.iter().filter(|post| !post.tags.is_empty()).map(|post| post.last_modified()).max(),
+        );
+
+        for s in &series {
+            let series_post_list: Vec<Post> =
+                s.posts.iter().map(|index| post_list[*index].clone()).collect();
+
+            write_listing(
+                &mut manifest,
+                &mut sitemap,
+                &format!("series/{}", s.slug),
+                &series_post_list,
+                pagination.series_page_size,
+                &[("page_series", JsonValue::String(s.title.clone()))],
+            );
+        }
+
+        for page in categories.values() {
+            let category_post_list: Vec<Post> =
+                page.posts.iter().map(|index| post_list[*index].clone()).collect();
+
+            write_listing(
+                &mut manifest,
+                &mut sitemap,
+                &format!("categories/{}", page.path),
+                &category_post_list,
+                pagination.tag_page_size,
+                &[
+                    ("page_category", JsonValue::String(page.name.clone())),
+                    ("breadcrumbs", json!(page.breadcrumbs)),
+                    ("subcategories", json!(page.subcategories_json(&categories))),
+                ],
+            );
+        }
+
+        // categories/ lists every category as a tree.
+        if !categories.is_empty() {
+            let categories_template = fs::read_to_string("template/categories.html")
+                .expect("Should have been able to read the file");
 
             let mut data = json!({
-                "posts": post_list[page_range],
+                "categories": category_tree(&categories),
+                "count": categories.len(),
                 "repo": repo,
-                "pages": pages,
                 "blog_title": blog_title,
                 "blog_description": blog_description,
-                "blog_url":blog_url,
+                "blog_url": blog_url,
                 "quipquick_version": VERSION,
                 "google_analytics": generate_google_analytics_id(&google_analytics_id),
-                "gallery": gallery
+                "gallery": gallery,
+                "feeds": feed_links,
+                "search": search.enabled,
+                "category_index": true,
             });
 
             if let Some(logo) = &logo {
-                fs::copy(&logo.url, format!("{}/{}", target_folder, logo.url)).unwrap();
                 data.as_object_mut()
                     .unwrap()
                     .insert("logo".to_string(), JsonValue::String(logo.url.clone()));
             }
 
-            if index > 0 {
-                let prev_path = if index - 1 == 0 {
-                    String::from("/index.html")
-                } else {
-                    format!("/index{}.html", index)
-                };
-                data.as_object_mut()
-                    .unwrap()
-                    .insert("prev".to_string(), JsonValue::String(prev_path));
-            }
-
-            if index < page_size - 1 {
-                let next_path = format!("/index{}.html", index + 2);
-                data.as_object_mut()
-                    .unwrap()
-                    .insert("next".to_string(), JsonValue::String(next_path));
-            }
+            let inputs = inputs_hash(&[
+                &data.to_string(),
+                &categories_template,
+                &partials,
+                base_path.prefix(),
+            ]);
+            manifest.write("categories/index.html", &inputs, || {
+                reg.render_template(&categories_template, &data).unwrap()
+            });
 
-            let index_rendered = reg.render_template(&index_template, &data).unwrap();
+            sitemap.add(
+                "categories/index.html",
+                post_list
+                    .iter()
+                    .filter(|post| !post.categories.is_empty())
+                    .map(|post| post.last_modified())
+                    .max(),
+            );
+        }
 
-            let output_path = if index == 0 {
-                format!("{}/index.html", target_folder)
-            } else {
-                format!("{}/index{}.html", target_folder, index + 1)
-            };
+        // archive/ lists every post by year and month, and archive/{year}/ the
+        // posts of one year.
+        let archive_template = fs::read_to_string("template/archive.html")
+            .expect("Should have been able to read the file");
+        let archive_years = archive(&post_list);
+        let year_links = |current: Option<i32>| {
+            archive_years
+                .iter()
+                .map(|y| {
+                    json!({
+                        "year": y.year,
+                        "count": y.count(),
+                        "link": base_path.url(&format!("/archive/{}/", y.year)),
+                        "current": Some(y.year) == current,
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
 
-            fs::write(output_path, index_rendered).unwrap();
+        let mut archive_pages = vec![(
+            String::from("archive/index.html"),
+            None,
+            archive_years.iter().collect::<Vec<_>>(),
+        )];
+        for y in &archive_years {
+            archive_pages.push((format!("archive/{}/index.html", y.year), Some(y.year), vec![y]));
         }
 
-        for t in tags {
-            let folder = t.0.as_str();
+        for (path, page_year, years) in archive_pages {
+            let mut data = json!({
+                "years": years.iter().map(|y| y.to_json(&base_path)).collect::<Vec<_>>(),
+                "year_links": year_links(page_year),
+                "count": years.iter().map(|y| y.count()).sum::<usize>(),
+                "repo": repo,
+                "blog_title": blog_title,
+                "blog_description": blog_description,
+                "blog_url": blog_url,
+                "quipquick_version": VERSION,
+                "google_analytics": generate_google_analytics_id(&google_analytics_id),
+                "gallery": gallery,
+                "feeds": feed_links,
+                "search": search.enabled,
+                "category_index": !categories.is_empty(),
+            });
 
-            let target_folder_exists =
-                Path::new(format!("{}/tags/{}", target_folder, folder).as_str()).exists();
+            let map = data.as_object_mut().unwrap();
 
-            if !target_folder_exists {
-                fs::create_dir_all(format!("{}/tags/{}", target_folder, folder).as_str())
-                    .expect(format!("Unable to create tag folder: {}.", &folder).as_str());
+            if let Some(logo) = &logo {
+                map.insert("logo".to_string(), JsonValue::String(logo.url.clone()));
             }
 
-            let tag_page_size: u32 = (t.1 .1.len() as f32 / PAGE_ITEM_COUNT as f32).ceil() as u32;
-
-            let mut tag_post_list: Vec<Post> = Vec::new();
-
-            for tid in &t.1 .1 {
-                tag_post_list.push(post_list[*tid as usize].clone());
+            if let Some(year) = page_year {
+                map.insert("page_year".to_string(), json!(year));
             }
 
-            for index in 0..tag_page_size {
-                let mut pages = Vec::new();
+            let inputs = inputs_hash(&[
+                &data.to_string(),
+                &archive_template,
+                &partials,
+                base_path.prefix(),
+            ]);
+            manifest.write(&path, &inputs, || {
+                reg.render_template(&archive_template, &data).unwrap()
+            });
 
-                for pindex in 0..tag_page_size {
-                    pages.push(json!({"id":pindex+1,"current":index == pindex, "link":if pindex ==0 {String::from("/index.html")} else {format!("/index{}.html",pindex+1)}}));
-                }
+            sitemap.add(
+                &path,
+                years
+                    .iter()
+                    .flat_map(|y| y.posts())
+                    .map(|post| post.last_modified())
+                    .max(),
+            );
+        }
 
-                let page_range = (index * PAGE_ITEM_COUNT) as usize
-                    ..cmp::min((index + 1) * PAGE_ITEM_COUNT, t.1 .1.len() as u32) as usize;
+        manifest.copy("template/style.css", "style.css");
 
-                let mut data = json!({
-                    "posts": tag_post_list[page_range],
-                    "repo": repo,
-                    "pages": pages,
-                    "blog_title": blog_title,
-                    "blog_description": blog_description,
-                    "blog_url":blog_url,
-                    "quipquick_version": VERSION,
-                    "google_analytics": generate_google_analytics_id(&google_analytics_id),
-                    "gallery": gallery
-                });
-
-                if let Some(logo) = &logo {
-                    fs::copy(&logo.url, format!("{}/{}", target_folder, logo.url)).unwrap();
-                    data.as_object_mut()
-                        .unwrap()
-                        .insert("logo".to_string(), JsonValue::String(logo.url.clone()));
-                }
+        if search.enabled {
+            manifest.write_content(
+                "search.json",
+                search_index(&post_list, &search.language, &base_path),
+            );
+            manifest.copy("template/search.js", "search.js");
 
-                if index > 0 {
-                    let prev_path = if index - 1 == 0 {
-                        format!("/tags/{}/index.html", t.0)
-                    } else {
-                        format!("/tags/{}/index{}.html", t.0, index)
-                    };
-                    data.as_object_mut()
-                        .unwrap()
-                        .insert("prev".to_string(), JsonValue::String(prev_path));
-                }
+            let search_template = fs::read_to_string("template/search.html")
+                .expect("Should have been able to read the file");
 
-                if index < tag_page_size - 1 {
-                    let next_path = format!("/tags/{}/index{}.html", t.0, index + 2);
-                    data.as_object_mut()
-                        .unwrap()
-                        .insert("next".to_string(), JsonValue::String(next_path));
-                }
+            let mut data = json!({
+                "repo": repo,
+                "blog_title": blog_title,
+                "blog_description": blog_description,
+                "blog_url": blog_url,
+                "quipquick_version": VERSION,
+                "google_analytics": generate_google_analytics_id(&google_analytics_id),
+                "gallery": gallery,
+                "feeds": feed_links,
+                "search_index": base_path.url("/search.json"),
+                "category_index": !categories.is_empty(),
+            });
 
+            if let Some(logo) = &logo {
                 data.as_object_mut()
                     .unwrap()
-                    .insert("page_tag".to_string(), JsonValue::String(t.1 .0.clone()));
-
-                let index_rendered = reg.render_template(&index_template, &data).unwrap();
-
-                let output_path = if index == 0 {
-                    format!("{}/tags/{}/index.html", target_folder, folder)
-                } else {
-                    format!("{}/tags/{}/index{}.html", target_folder, folder, index + 1)
-                };
-
-                fs::write(output_path, index_rendered).unwrap();
+                    .insert("logo".to_string(), JsonValue::String(logo.url.clone()));
             }
+
+            let inputs = inputs_hash(&[
+                &data.to_string(),
+                &search_template,
+                &partials,
+                base_path.prefix(),
+            ]);
+            manifest.write("search/index.html", &inputs, || {
+                reg.render_template(&search_template, &data).unwrap()
+            });
         }
 
-        fs::write(
-            format!("{}/current_time.txt", target_folder).as_str(),
-            format!("{}", current_time.format("%Y-%m-%d %H:%M:%S")),
-        )
-        .unwrap();
-        fs::copy("template/style.css", format!("{}/style.css", target_folder)).unwrap();
+        if let Some(highlighter) = &render_options.highlighter {
+            manifest.write_content("highlight.css", highlighter.css());
+        }
 
         if let Some(g) = gallery {
-            crate::gallery::generate_gallery(
+            let newest = crate::gallery::generate_gallery(
                 &target_folder,
-                &g,
+                g,
                 &repo,
                 &blog_title,
                 &blog_description,
                 &blog_url,
                 VERSION,
                 &google_analytics_id,
+                &base_path,
+            );
+            manifest.record_folder(g, "");
+            sitemap.add(&format!("{}/index.html", g), newest);
+        }
+
+        manifest.write_content("sitemap.xml", sitemap.to_xml());
+        if robots.generate {
+            manifest.write_content(
+                "robots.txt",
+                robots.to_txt(&base_path, &format!("{}/sitemap.xml", blog_url)),
+            );
+        }
+
+        // The build time shown on pages only moves when the site changed.
+        if manifest.has_changes() || !manifest.keep("current_time.txt") {
+            manifest.write_content(
+                "current_time.txt",
+                format!("{}", current_time.format("%Y-%m-%d %H:%M:%S")),
+            );
+        }
+
+        manifest.remove_orphans();
+        manifest.save(&render_options.cache);
+        // A preview leaves the cache as `pub` needs it.
+        if !options.preview {
+            render_options.cache.prune();
+        }
+
+        if math_errors > 0 {
+            println!(
+                "Published with {} TeX error(s), see the messages above.",
+                math_errors
             );
         }
     }
+
+    Ok(())
 }
@@ -0,0 +1,181 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::post::Post;
+
+/// Related posts settings, read from `[related]` in quipquick.toml.
+#[derive(Debug, Clone)]
+pub struct RelatedOptions {
+    /// How many related posts a post lists, 0 turns them off.
+    pub count: usize,
+    /// Weight of the share of tags two posts have in common.
+    pub tag_weight: f64,
+    /// Weight of the TF-IDF similarity of the posts' text.
+    pub text_weight: f64,
+}
+
+impl Default for RelatedOptions {
+    fn default() -> Self {
+        RelatedOptions {
+            count: 3,
+            tag_weight: 1.0,
+            text_weight: 1.0,
+        }
+    }
+}
+
+impl RelatedOptions {
+    /// Reads `[related]` from quipquick.toml, with defaults for what isn't set.
+    pub fn from_config(config: &toml::Value) -> Result<Self, String> {
+        let mut related = RelatedOptions::default();
+        let Some(config) = config.get("related").and_then(|value| value.as_table()) else {
+            return Ok(related);
+        };
+
+        if let Some(count) = config.get("count") {
+            match count.as_integer() {
+                Some(count) if count >= 0 => related.count = count as usize,
+                _ => return Err("Related count should be a number, 0 or more.".to_string()),
+            }
+        }
+        for (key, weight) in [
+            ("tag_weight", &mut related.tag_weight),
+            ("text_weight", &mut related.text_weight),
+        ] {
+            if let Some(value) = config.get(key) {
+                match value
+                    .as_float()
+                    .or_else(|| value.as_integer().map(|i| i as f64))
+                {
+                    Some(value) if value >= 0.0 => *weight = value,
+                    _ => return Err(format!("Related {} should be a number, 0 or more.", key)),
+                }
+            }
+        }
+
+        Ok(related)
+    }
+}
+
+/// A post listed under another one's related posts.
+#[derive(Debug, Clone, Serialize)]
+pub struct RelatedPost {
+    pub title: String,
+    pub src: String,
+    pub description: String,
+    pub date: String,
+}
+
+/// The posts related posts are picked from, with the TF-IDF vectors of their
+/// terms.
+pub struct RelatedIndex<'a> {
+    candidates: &'a [Post],
+    idf: HashMap<&'a str, f64>,
+    vectors: Vec<HashMap<&'a str, f64>>,
+}
+
+impl<'a> RelatedIndex<'a> {
+    pub fn new(candidates: &'a [Post]) -> Self {
+        let mut document_frequency: HashMap<&str, usize> = HashMap::new();
+        for post in candidates {
+            for term in post.terms.keys() {
+                *document_frequency.entry(term).or_insert(0) += 1;
+            }
+        }
+
+        // Smoothed, so terms found in every post still count a little. Blogs
+        // with a handful of posts would hardly relate otherwise.
+        let count = candidates.len() as f64;
+        let idf: HashMap<&str, f64> = document_frequency
+            .into_iter()
+            .map(|(term, df)| (term, ((1.0 + count) / (1.0 + df as f64)).ln() + 1.0))
+            .collect();
+
+        let mut index = RelatedIndex {
+            candidates,
+            idf,
+            vectors: Vec::new(),
+        };
+        index.vectors = candidates
+            .iter()
+            .map(|post| index.vector(&post.terms))
+            .collect();
+        index
+    }
+
+    /// A post's terms weighted by TF-IDF, scaled to a length of 1 so the dot
+    /// product of two vectors is their cosine similarity.
+    fn vector(&self, terms: &BTreeMap<String, usize>) -> HashMap<&'a str, f64> {
+        let mut vector: HashMap<&'a str, f64> = terms
+            .iter()
+            .filter_map(|(term, count)| {
+                let (term, idf) = self.idf.get_key_value(term.as_str())?;
+                Some((*term, (1.0 + (*count as f64).ln()) * idf))
+            })
+            .collect();
+
+        let length = vector.values().map(|w| w * w).sum::<f64>().sqrt();
+        for weight in vector.values_mut() {
+            *weight /= length;
+        }
+
+        vector
+    }
+
+    /// The posts most related to `post`, best first. Posts with equal scores
+    /// keep the order of the candidates, newest first.
+    pub fn related(&self, post: &Post, options: &RelatedOptions) -> Vec<RelatedPost> {
+        if options.count == 0 {
+            return Vec::new();
+        }
+
+        let vector = self.vector(&post.terms);
+        let tags: HashSet<&str> = post.tags.iter().map(|t| t.slug.as_str()).collect();
+
+        let mut scores: Vec<(usize, f64)> = self
+            .candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, candidate)| candidate.src != post.src)
+            .map(|(index, candidate)| {
+                let candidate_tags: HashSet<&str> =
+                    candidate.tags.iter().map(|t| t.slug.as_str()).collect();
+                let union = tags.union(&candidate_tags).count();
+                let shared_tags = if union == 0 {
+                    0.0
+                } else {
+                    tags.intersection(&candidate_tags).count() as f64 / union as f64
+                };
+
+                let text_similarity: f64 = vector
+                    .iter()
+                    .filter_map(|(term, weight)| {
+                        self.vectors[index].get(term).map(|other| weight * other)
+                    })
+                    .sum();
+
+                (
+                    index,
+                    options.tag_weight * shared_tags + options.text_weight * text_similarity,
+                )
+            })
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        scores
+            .into_iter()
+            .take(options.count)
+            .map(|(index, _)| {
+                let candidate = &self.candidates[index];
+                RelatedPost {
+                    title: titlecase::titlecase(&candidate.title),
+                    src: candidate.src.clone(),
+                    description: candidate.description.clone(),
+                    date: candidate.date.format("%Y-%m-%d").to_string(),
+                }
+            })
+            .collect()
+    }
+}
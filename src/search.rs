@@ -0,0 +1,116 @@
+use rust_stemmers::{Algorithm, Stemmer};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+use crate::base_path::BasePath;
+use crate::post::Post;
+
+/// Version of the search.json format, bumped when it changes.
+const SEARCH_INDEX_VERSION: u32 = 1;
+
+/// Search settings, read from `[search]` in quipquick.toml.
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// Write search.json and the search page. Off by default, so existing blogs
+    /// don't grow a search page, or lose a content folder named `search`.
+    pub enabled: bool,
+    /// The language posts are written in, which decides how words are stemmed.
+    pub language: String,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            enabled: false,
+            language: String::from("english"),
+        }
+    }
+}
+
+impl SearchOptions {
+    /// Reads `[search]` from quipquick.toml, with defaults for what isn't set.
+    /// The language has to be one `stemmer_algorithm` knows.
+    pub fn from_config(config: &toml::Value) -> Result<Self, String> {
+        let mut search = SearchOptions::default();
+        if let Some(config) = config.get("search").and_then(|value| value.as_table()) {
+            if let Some(enabled) = config.get("enabled").and_then(|value| value.as_bool()) {
+                search.enabled = enabled;
+            }
+            if let Some(language) = config.get("language").and_then(|value| value.as_str()) {
+                search.language = language.to_string();
+            }
+        }
+
+        if stemmer_algorithm(&search.language).is_none() {
+            return Err(format!("Search language {} isn't supported.", search.language));
+        }
+
+        Ok(search)
+    }
+}
+
+/// The Snowball stemmer for a language, e.g. `english` or `french`.
+pub fn stemmer_algorithm(language: &str) -> Option<Algorithm> {
+    match language.to_lowercase().as_str() {
+        "arabic" => Some(Algorithm::Arabic),
+        "danish" => Some(Algorithm::Danish),
+        "dutch" => Some(Algorithm::Dutch),
+        "english" => Some(Algorithm::English),
+        "finnish" => Some(Algorithm::Finnish),
+        "french" => Some(Algorithm::French),
+        "german" => Some(Algorithm::German),
+        "greek" => Some(Algorithm::Greek),
+        "hungarian" => Some(Algorithm::Hungarian),
+        "italian" => Some(Algorithm::Italian),
+        "norwegian" => Some(Algorithm::Norwegian),
+        "portuguese" => Some(Algorithm::Portuguese),
+        "romanian" => Some(Algorithm::Romanian),
+        "russian" => Some(Algorithm::Russian),
+        "spanish" => Some(Algorithm::Spanish),
+        "swedish" => Some(Algorithm::Swedish),
+        "tamil" => Some(Algorithm::Tamil),
+        "turkish" => Some(Algorithm::Turkish),
+        _ => None,
+    }
+}
+
+/// Splits `text` into lowercase words of two characters or more, and counts
+/// their stems.
+pub fn terms(text: &str, stemmer: &Stemmer) -> BTreeMap<String, usize> {
+    let mut terms = BTreeMap::new();
+
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+    {
+        let stem = stemmer.stem(&word.to_lowercase()).to_string();
+        *terms.entry(stem).or_insert(0) += 1;
+    }
+
+    terms
+}
+
+/// search.json, see "Search" in the README for the format.
+pub fn search_index(posts: &[Post], language: &str, base_path: &BasePath) -> String {
+    let posts: Vec<_> = posts
+        .iter()
+        .filter(|post| !post.noindex)
+        .map(|post| {
+            json!({
+                "url": base_path.url(&format!("/{}/", post.src)),
+                "title": titlecase::titlecase(&post.title),
+                "description": post.description,
+                "tags": post.tags.iter().map(|t| &t.tag).collect::<Vec<_>>(),
+                "date": post.date.format("%Y-%m-%d").to_string(),
+                "terms": post.terms,
+            })
+        })
+        .collect();
+
+    json!({
+        "version": SEARCH_INDEX_VERSION,
+        "language": language.to_lowercase(),
+        "posts": posts,
+    })
+    .to_string()
+}
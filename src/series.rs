@@ -0,0 +1,109 @@
+use serde::Serialize;
+use slugify::slugify;
+use std::collections::BTreeMap;
+
+use crate::post::Post;
+
+/// A post in a series, as linked from the series box.
+#[derive(Debug, Clone, Serialize)]
+pub struct SeriesPart {
+    pub title: String,
+    pub src: String,
+    pub current: bool,
+}
+
+/// What a post's page knows about its series.
+#[derive(Debug, Clone, Serialize)]
+pub struct Series {
+    pub slug: String,
+    pub title: String,
+    /// Every part in reading order, the current post highlighted.
+    pub parts: Vec<SeriesPart>,
+    /// The current post's part number, counting from 1. Unlisted posts aren't
+    /// one of the parts.
+    pub part: Option<usize>,
+    pub count: usize,
+    pub prev: Option<SeriesPart>,
+    pub next: Option<SeriesPart>,
+}
+
+/// The posts of one series, in reading order.
+pub struct SeriesPosts {
+    pub slug: String,
+    pub title: String,
+    /// Indices into the post list.
+    pub posts: Vec<usize>,
+}
+
+/// Groups `posts` by their `series`, sorted by slug. Parts are ordered by
+/// `series_order`, and by date for parts without one, which come last. Parts
+/// sharing a `series_order` are reported to `log`.
+pub fn find_series(posts: &[Post], log: &mut Vec<String>) -> Vec<SeriesPosts> {
+    let mut series: BTreeMap<String, SeriesPosts> = BTreeMap::new();
+
+    for (index, post) in posts.iter().enumerate() {
+        let Some(title) = &post.series_title else {
+            continue;
+        };
+
+        let slug = slugify!(title);
+        series
+            .entry(slug.clone())
+            .or_insert_with(|| SeriesPosts {
+                slug,
+                title: title.clone(),
+                posts: Vec::new(),
+            })
+            .posts
+            .push(index);
+    }
+
+    for s in series.values_mut() {
+        s.posts.sort_by_key(|index| {
+            let post = &posts[*index];
+            (post.series_order.is_none(), post.series_order, post.date)
+        });
+
+        for pair in s.posts.windows(2) {
+            let (a, b) = (&posts[pair[0]], &posts[pair[1]]);
+            if let Some(order) = a.series_order
+                && a.series_order == b.series_order
+            {
+                log.push(format!(
+                    "Posts {} and {} are both part {} of series {}.",
+                    a.src, b.src, order, s.title
+                ));
+            }
+        }
+    }
+
+    series.into_values().collect()
+}
+
+impl SeriesPosts {
+    /// The series box for the post in `src`, which may be an unlisted post
+    /// that isn't one of `posts`.
+    pub fn navigation(&self, posts: &[Post], src: &str) -> Series {
+        let parts: Vec<SeriesPart> = self
+            .posts
+            .iter()
+            .map(|index| SeriesPart {
+                title: titlecase::titlecase(&posts[*index].title),
+                src: posts[*index].src.clone(),
+                current: posts[*index].src == src,
+            })
+            .collect();
+
+        let position = parts.iter().position(|part| part.current);
+
+        Series {
+            slug: self.slug.clone(),
+            title: self.title.clone(),
+            part: position.map(|p| p + 1),
+            count: parts.len(),
+            prev: position.filter(|p| *p > 0).map(|p| parts[p - 1].clone()),
+            next: position.and_then(|p| parts.get(p + 1).cloned()),
+            parts,
+        }
+    }
+}
@@ -0,0 +1,430 @@
+use notify::{Event, RecursiveMode, Watcher};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Request, Response, Server};
+
+use crate::publish::{PublishOptions, find_drafts, publish, reserved_folders};
+use crate::search::SearchOptions;
+
+const RELOAD_PATH: &str = "/__quipquick/reload";
+
+/// Injected into every page. It asks the server for a build newer than the one
+/// the page came from, and reloads once there is one. The server holds the
+/// request until then, or answers with the same build after a while.
+const RELOAD_SCRIPT: &str = "<script>
+(function () {
+    var version = {version};
+    function wait() {
+        fetch('/__quipquick/reload?version=' + version)
+            .then(function (response) { return response.text(); })
+            .then(function (latest) {
+                if (Number(latest) !== version) {
+                    location.reload();
+                } else {
+                    wait();
+                }
+            })
+            .catch(function () { setTimeout(wait, 1000); });
+    }
+    wait();
+})();
+</script>";
+
+/// How many requests for files are served at once. Pages waiting for a
+/// rebuild don't take a worker, see `answer_reloads`.
+const WORKERS: usize = 4;
+
+/// How long a page waiting for a rebuild is held before it's answered with
+/// the same build.
+const RELOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+enum Reload {
+    /// A page that came from build `version` waits for a newer one.
+    Wait(Request, u64),
+    Rebuilt,
+}
+
+/// Counts builds, so waiting pages can tell when to reload.
+struct BuildState {
+    version: AtomicU64,
+    reloads: mpsc::Sender<Reload>,
+}
+
+impl BuildState {
+    fn current(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    fn bump(&self) {
+        self.version.fetch_add(1, Ordering::SeqCst);
+        let _ = self.reloads.send(Reload::Rebuilt);
+    }
+
+    fn wait_for_build(&self, request: Request, version: u64) {
+        let _ = self.reloads.send(Reload::Wait(request, version));
+    }
+}
+
+/// Holds the reload requests of open pages, and answers them with the latest
+/// build once there is a newer one than theirs, or after `RELOAD_TIMEOUT`.
+fn answer_reloads(reloads: mpsc::Receiver<Reload>, state: &BuildState) {
+    let mut waiting: Vec<(Request, u64, Instant)> = Vec::new();
+
+    loop {
+        match reloads.recv_timeout(Duration::from_secs(1)) {
+            Ok(Reload::Wait(request, version)) => {
+                waiting.push((request, version, Instant::now()));
+            }
+            Ok(Reload::Rebuilt) | Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+
+        let latest = state.current();
+        let (done, still_waiting) = waiting.into_iter().partition(|(_, version, since)| {
+            *version != latest || since.elapsed() >= RELOAD_TIMEOUT
+        });
+        waiting = still_waiting;
+
+        for (request, _, _) in done {
+            let _ = request.respond(
+                Response::from_string(latest.to_string())
+                    .with_header(header("Content-Type", "text/plain")),
+            );
+        }
+    }
+}
+
+/// A failed build is reported and leaves the previous one in place, so the
+/// server keeps running until the next change. Returns whether the build
+/// succeeded.
+fn build(options: &PublishOptions) -> bool {
+    let built = match panic::catch_unwind(AssertUnwindSafe(|| publish(options))) {
+        Ok(Ok(())) => true,
+        Ok(Err(error)) => {
+            println!("{}", error);
+            false
+        }
+        Err(_) => false,
+    };
+    if !built {
+        println!("Build failed, the preview will update after the next change.");
+    }
+    built
+}
+
+/// What a build reads: quipquick.toml, the template folder, the logo, the
+/// gallery, and the content and draft folders. Other files in the blog's
+/// folder, like a target folder inside it, don't trigger rebuilds.
+fn watched_paths(root: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![root.join("quipquick.toml"), root.join("template")];
+
+    let Some(config) = fs::read_to_string("quipquick.toml")
+        .ok()
+        .and_then(|contents| toml::from_str::<toml::Value>(&contents).ok())
+    else {
+        return paths;
+    };
+
+    let mut folders: Vec<String> = config
+        .get("content")
+        .and_then(|value| value.as_array())
+        .map(|content| {
+            content
+                .iter()
+                .filter_map(|folder| folder.as_str())
+                .map(|folder| folder.to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    let search_enabled = SearchOptions::from_config(&config).is_ok_and(|search| search.enabled);
+    let gallery = config.get("gallery").and_then(|value| value.as_str());
+    folders.extend(find_drafts(&folders, &reserved_folders(search_enabled, gallery)));
+
+    for key in ["logo", "gallery"] {
+        if let Some(path) = config.get(key).and_then(|value| value.as_str()) {
+            folders.push(path.to_string());
+        }
+    }
+
+    paths.extend(folders.iter().map(|folder| root.join(folder)));
+    paths
+}
+
+/// The blog's folder is watched on its own, for changes to quipquick.toml and
+/// for new folders that may be drafts, and each watched path with everything in it.
+fn watch(watcher: &mut impl Watcher, root: &Path, paths: &[PathBuf]) {
+    watcher.watch(root, RecursiveMode::NonRecursive).unwrap();
+    for path in paths.iter().filter(|path| path.exists()) {
+        if let Err(error) = watcher.watch(path, RecursiveMode::Recursive) {
+            println!("Unable to watch {}: {}", path.display(), error);
+        }
+    }
+}
+
+fn unwatch(watcher: &mut impl Watcher, root: &Path, paths: &[PathBuf]) {
+    let _ = watcher.unwatch(root);
+    for path in paths {
+        let _ = watcher.unwatch(path);
+    }
+}
+
+/// Whether a file system event should trigger a rebuild: it has to be about a
+/// watched path, or a new folder in the blog's folder. Hidden files and
+/// folders, such as .git, .quipquick-cache and editor swap files, are ignored.
+fn is_relevant(event: &notify::Result<Event>, root: &Path, watched: &[PathBuf]) -> bool {
+    let Ok(event) = event else {
+        return false;
+    };
+
+    if event.kind.is_access() || event.kind.is_other() {
+        return false;
+    }
+
+    event.paths.iter().any(|path| {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let hidden = relative.components().any(|c| match c {
+            Component::Normal(name) => {
+                let name = name.to_string_lossy();
+                name.starts_with('.') || name.ends_with('~')
+            }
+            _ => false,
+        });
+
+        !hidden
+            && (watched.iter().any(|w| path.starts_with(w))
+                || (path.parent() == Some(root) && path.is_dir()))
+    })
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .as_deref()
+    {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn not_found(request: Request) {
+    let _ = request.respond(Response::from_string("Not found").with_status_code(404));
+}
+
+fn handle(request: Request, root: &Path, state: &BuildState) {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((url.as_str(), ""));
+
+    if path == RELOAD_PATH {
+        let version = query
+            .split('&')
+            .find_map(|p| p.strip_prefix("version="))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        state.wait_for_build(request, version);
+        return;
+    }
+
+    let path = percent_decode(path);
+    if path.split('/').any(|p| p == "..") {
+        return not_found(request);
+    }
+
+    let mut file = root.join(path.trim_start_matches('/'));
+    if file.is_dir() {
+        // Relative links in a post only work from `/post/`, not `/post`.
+        if !path.ends_with('/') {
+            let _ = request.respond(
+                Response::empty(301).with_header(header("Location", &format!("{}/", path))),
+            );
+            return;
+        }
+        file = file.join("index.html");
+    }
+
+    let Ok(mut body) = fs::read(&file) else {
+        return not_found(request);
+    };
+
+    let content_type = content_type(&file);
+    if content_type.starts_with("text/html") {
+        let html = String::from_utf8_lossy(&body).to_string();
+        let script = RELOAD_SCRIPT.replace("{version}", &state.current().to_string());
+        let html = match html.rfind("</body>") {
+            Some(end) => format!("{}{}{}", &html[..end], script, &html[end..]),
+            None => html + &script,
+        };
+        body = html.into_bytes();
+    }
+
+    let _ = request.respond(
+        Response::from_data(body)
+            .with_header(header("Content-Type", content_type))
+            .with_header(header("Cache-Control", "no-store")),
+    );
+}
+
+/// Builds the blog, drafts included, into a temporary folder and serves it on
+/// `port`. The blog is rebuilt when anything in it changes, and open pages
+/// reload themselves.
+pub fn serve(port: u16, jobs: Option<usize>) {
+    if !Path::new("quipquick.toml").exists() {
+        println!("No quipquick.toml here, run serve from your blog's folder.");
+        return;
+    }
+
+    let server = match Server::http(("127.0.0.1", port)) {
+        Ok(server) => server,
+        Err(error) => {
+            println!("Unable to listen on port {}: {}", port, error);
+            return;
+        }
+    };
+
+    // Removed when serve returns or panics. Ctrl+C and termination exit
+    // without unwinding, so the handler below removes it then.
+    let target_dir = tempfile::Builder::new()
+        .prefix("quipquick-serve-")
+        .tempdir()
+        .unwrap();
+    let target: PathBuf = target_dir.path().to_path_buf();
+
+    let options = PublishOptions {
+        target: target.to_string_lossy().to_string(),
+        force_overwrite_theme: false,
+        no_cache: false,
+        jobs,
+        prefix: None,
+        preview: true,
+    };
+
+    build(&options);
+
+    let (reloads, waiting) = mpsc::channel();
+    let state = Arc::new(BuildState {
+        version: AtomicU64::new(0),
+        reloads,
+    });
+
+    let reload_state = state.clone();
+    thread::spawn(move || answer_reloads(waiting, &reload_state));
+
+    let cleanup_target = target.clone();
+    ctrlc::set_handler(move || {
+        let _ = fs::remove_dir_all(&cleanup_target);
+        std::process::exit(0);
+    })
+    .unwrap();
+
+    let root = std::env::current_dir().unwrap().canonicalize().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).unwrap();
+    let mut watched = watched_paths(&root);
+    watch(&mut watcher, &root, &watched);
+
+    let watch_state = state.clone();
+    thread::spawn(move || {
+        while let Ok(event) = rx.recv() {
+            if !is_relevant(&event, &root, &watched) {
+                continue;
+            }
+
+            // Saving often touches several files, wait for things to settle.
+            while rx.recv_timeout(Duration::from_millis(200)).is_ok() {}
+
+            println!("Change detected, rebuilding.");
+            // Open pages keep what they show until a build succeeds.
+            if build(&options) {
+                watch_state.bump();
+            }
+
+            // The content list, the logo or the drafts may have changed.
+            let paths = watched_paths(&root);
+            if paths != watched {
+                unwatch(&mut watcher, &root, &watched);
+                watched = paths;
+                watch(&mut watcher, &root, &watched);
+            }
+        }
+    });
+
+    println!(
+        "Serving a preview at http://127.0.0.1:{}/, press Ctrl+C to stop.",
+        port
+    );
+
+    let server = Arc::new(server);
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|_| {
+            let server = server.clone();
+            let state = state.clone();
+            let target = target.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(request, &target, &state);
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+use chrono::{DateTime, Local};
+
+use crate::base_path::BasePath;
+
+struct SitemapUrl {
+    loc: String,
+    lastmod: Option<String>,
+}
+
+/// The pages listed in sitemap.xml.
+pub struct Sitemap {
+    blog_url: String,
+    urls: Vec<SitemapUrl>,
+}
+
+impl Sitemap {
+    /// `blog_url` already includes the prefix.
+    pub fn new(blog_url: &str) -> Self {
+        Sitemap {
+            blog_url: blog_url.to_string(),
+            urls: Vec::new(),
+        }
+    }
+
+    /// Adds the page at `path`, relative to the target folder. Folder pages are
+    /// listed by their folder, e.g. `tags/rust/index.html` as `tags/rust/`.
+    pub fn add(&mut self, path: &str, lastmod: Option<DateTime<Local>>) {
+        let path = path.strip_suffix("index.html").unwrap_or(path);
+        self.urls.push(SitemapUrl {
+            loc: format!("{}/{}", self.blog_url, path),
+            lastmod: lastmod.map(|date| date.format("%Y-%m-%d").to_string()),
+        });
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+        );
+
+        let mut urls: Vec<&SitemapUrl> = self.urls.iter().collect();
+        urls.sort_by(|a, b| a.loc.cmp(&b.loc));
+
+        for url in urls {
+            xml.push_str("  <url>\n");
+            xml.push_str(&format!(
+                "    <loc>{}</loc>\n",
+                html_escape::encode_text(&url.loc)
+            ));
+            if let Some(lastmod) = &url.lastmod {
+                xml.push_str(&format!("    <lastmod>{}</lastmod>\n", lastmod));
+            }
+            xml.push_str("  </url>\n");
+        }
+
+        xml.push_str("</urlset>\n");
+        xml
+    }
+}
+
+/// What goes into robots.txt, read from `[robots]` in quipquick.toml.
+#[derive(Debug, Clone)]
+pub struct RobotsOptions {
+    /// `false` leaves robots.txt out, e.g. when the site has its own.
+    pub generate: bool,
+    /// Paths crawlers should stay out of, relative to the blog.
+    pub disallow: Vec<String>,
+    /// Appended as is, e.g. rules for a specific crawler.
+    pub extra: String,
+}
+
+impl Default for RobotsOptions {
+    fn default() -> Self {
+        RobotsOptions {
+            generate: true,
+            disallow: Vec::new(),
+            extra: String::new(),
+        }
+    }
+}
+
+impl RobotsOptions {
+    /// Reads `[robots]` from quipquick.toml, with defaults for what isn't set.
+    pub fn from_config(config: &toml::Value) -> Result<Self, String> {
+        let mut robots = RobotsOptions::default();
+        let Some(config) = config.get("robots").and_then(|value| value.as_table()) else {
+            return Ok(robots);
+        };
+
+        if let Some(generate) = config.get("generate").and_then(|value| value.as_bool()) {
+            robots.generate = generate;
+        }
+        if let Some(disallow) = config.get("disallow") {
+            let Some(disallow) = disallow.as_array() else {
+                return Err("Robots disallow should be a list of paths.".to_string());
+            };
+            robots.disallow = disallow
+                .iter()
+                .filter_map(|path| path.as_str())
+                .map(|path| path.to_string())
+                .collect();
+        }
+        if let Some(extra) = config.get("extra").and_then(|value| value.as_str()) {
+            robots.extra = extra.to_string();
+        }
+
+        Ok(robots)
+    }
+}
+
+impl RobotsOptions {
+    pub fn to_txt(&self, base_path: &BasePath, sitemap_url: &str) -> String {
+        let mut txt = String::from("User-agent: *\n");
+        if self.disallow.is_empty() {
+            txt.push_str("Allow: /\n");
+        }
+        for path in &self.disallow {
+            txt.push_str(&format!("Disallow: {}\n", base_path.url(path)));
+        }
+
+        if !self.extra.trim().is_empty() {
+            txt.push('\n');
+            txt.push_str(self.extra.trim());
+            txt.push('\n');
+        }
+
+        txt.push_str(&format!("\nSitemap: {}\n", sitemap_url));
+        txt
+    }
+}
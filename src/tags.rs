@@ -0,0 +1,166 @@
+use serde_json::{Value, json};
+use slugify::slugify;
+use std::collections::HashMap;
+
+use crate::base_path::BasePath;
+use crate::post::Tag;
+
+/// A tag's settings, read from `[tags.<slug>]` in quipquick.toml.
+#[derive(Debug, Clone, Default)]
+pub struct TagInfo {
+    /// Shown instead of the lowercased label.
+    pub name: Option<String>,
+    /// Shown at the top of the tag's page and in the tag index.
+    pub description: Option<String>,
+    /// Other labels for the same tag, e.g. `rustlang` for `rust`.
+    pub aliases: Vec<String>,
+}
+
+/// The tags configured in quipquick.toml, and the aliases merged into them.
+#[derive(Debug, Default)]
+pub struct TagRegistry {
+    tags: HashMap<String, TagInfo>,
+    /// Alias slugs to the slugs of their tags.
+    aliases: HashMap<String, String>,
+}
+
+impl TagRegistry {
+    /// Adds the tag `slug`. A label can't be both a tag and an alias, or an
+    /// alias of two tags.
+    pub fn insert(&mut self, slug: &str, info: TagInfo) -> Result<(), String> {
+        let slug = slugify!(slug);
+
+        if let Some(tag) = self.aliases.get(&slug) {
+            return Err(format!("Tag {} is also an alias of tag {}.", slug, tag));
+        }
+
+        for alias in &info.aliases {
+            let alias = slugify!(alias);
+            if alias == slug {
+                continue;
+            }
+            if self.tags.contains_key(&alias) {
+                return Err(format!("Alias {} of tag {} is a tag itself.", alias, slug));
+            }
+            if let Some(tag) = self.aliases.get(&alias) {
+                return Err(format!(
+                    "Alias {} belongs to both tag {} and tag {}.",
+                    alias, tag, slug
+                ));
+            }
+            self.aliases.insert(alias, slug.clone());
+        }
+
+        self.tags.insert(slug, info);
+        Ok(())
+    }
+
+    /// The tag a post's `label` stands for, after resolving aliases.
+    pub fn tag(&self, label: &str) -> Tag {
+        let slug = slugify!(label);
+
+        match self.aliases.get(&slug) {
+            Some(canonical) => Tag {
+                slug: canonical.clone(),
+                tag: self.name(canonical).unwrap_or(canonical).to_string(),
+            },
+            None => Tag {
+                tag: self
+                    .name(&slug)
+                    .map(|name| name.to_string())
+                    .unwrap_or_else(|| label.to_lowercase()),
+                slug,
+            },
+        }
+    }
+
+    /// Reads the `[tags.<slug>]` tables of quipquick.toml.
+    pub fn from_config(config: &toml::Value) -> Result<Self, String> {
+        let mut registry = TagRegistry::default();
+        let Some(tags) = config.get("tags").and_then(|value| value.as_table()) else {
+            return Ok(registry);
+        };
+
+        for (slug, tag) in tags {
+            let Some(tag) = tag.as_table() else {
+                return Err(format!("Tag {} should be a table, e.g. [tags.{}].", slug, slug));
+            };
+
+            let info = TagInfo {
+                name: tag
+                    .get("name")
+                    .and_then(|value| value.as_str())
+                    .map(|name| name.to_string()),
+                description: tag
+                    .get("description")
+                    .and_then(|value| value.as_str())
+                    .map(|description| description.to_string()),
+                aliases: tag
+                    .get("aliases")
+                    .and_then(|value| value.as_array())
+                    .map(|aliases| {
+                        aliases
+                            .iter()
+                            .filter_map(|alias| alias.as_str())
+                            .map(|alias| alias.to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            };
+
+            registry.insert(slug, info)?;
+        }
+
+        Ok(registry)
+    }
+
+    fn name(&self, slug: &str) -> Option<&str> {
+        self.tags.get(slug).and_then(|info| info.name.as_deref())
+    }
+
+    pub fn description(&self, slug: &str) -> Option<&str> {
+        self.tags
+            .get(slug)
+            .and_then(|info| info.description.as_deref())
+    }
+}
+
+/// The `tags` of tags.html: every tag with its post count and a cloud weight
+/// from 1 to 5, sorted by name. `tags` holds each slug's name and count.
+pub fn tag_index(
+    tags: &[(String, String, usize)],
+    registry: &TagRegistry,
+    base_path: &BasePath,
+) -> Vec<Value> {
+    let counts = tags.iter().map(|(_, _, count)| *count as f64);
+    let min = counts.clone().fold(f64::INFINITY, f64::min).ln();
+    let max = counts.fold(0.0, f64::max).ln();
+
+    let mut tags: Vec<&(String, String, usize)> = tags.iter().collect();
+    tags.sort_by(|a, b| {
+        a.1.to_lowercase()
+            .cmp(&b.1.to_lowercase())
+            .then(a.0.cmp(&b.0))
+    });
+
+    tags.into_iter()
+        .map(|(slug, tag, count)| {
+            // Counts are spread on a log scale, so a few big tags don't leave
+            // every other tag at the smallest size.
+            let weight = if max > min {
+                1 + ((((*count as f64).ln() - min) / (max - min)) * 4.0).round() as u32
+            } else {
+                3
+            };
+
+            json!({
+                "slug": slug,
+                "tag": tag,
+                "description": registry.description(slug),
+                "count": count,
+                "link": base_path.url(&format!("/tags/{}/", slug)),
+                "weight": weight,
+            })
+        })
+        .collect()
+}
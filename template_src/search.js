@@ -0,0 +1,119 @@
+// Searches the posts listed in search.json, see the README for its format.
+// Words are stemmed when the index is built, and browsers can't stem them the
+// same way. So a query word matches a term it starts with ("running" matches
+// "run"), and the word still being typed also matches the terms it's the start
+// of ("equ" matches "equat"). Prefixes shorter than MIN_PREFIX match nothing,
+// so "is" doesn't match "island" and "island" doesn't match "is".
+(function () {
+    var input = document.getElementById('search-input');
+    var status = document.getElementById('search-status');
+    var results = document.getElementById('search-results');
+    var posts = [];
+    var MIN_PREFIX = 3;
+
+    function words(text) {
+        return text.toLowerCase().split(/[^\p{L}\p{N}]+/u).filter(function (word) {
+            return word.length > 1;
+        });
+    }
+
+    function matches(word, term, typing) {
+        if (word === term) {
+            return true;
+        }
+        if (term.length >= MIN_PREFIX && word.startsWith(term)) {
+            return true;
+        }
+        return typing && word.length >= MIN_PREFIX && term.startsWith(word);
+    }
+
+    // How well a post matches one query word, 0 if it doesn't. `typing` is
+    // set for the last word while the query doesn't end with a space.
+    function wordScore(post, word, typing) {
+        var match = function (w) { return matches(word, w, typing); };
+        var score = 0;
+        for (var term in post.terms) {
+            if (match(term)) {
+                score += post.terms[term];
+            }
+        }
+        if (words(post.title).some(match)) {
+            score += 10;
+        }
+        if (post.tags.some(function (tag) { return words(tag).some(match); })) {
+            score += 5;
+        }
+        if (words(post.description).some(match)) {
+            score += 3;
+        }
+        return score;
+    }
+
+    function item(post) {
+        var container = document.createElement('div');
+        container.className = 'content-item';
+        var link = document.createElement('a');
+        link.href = post.url;
+        var title = document.createElement('p');
+        title.className = 'content-item-title';
+        title.textContent = post.title;
+        var description = document.createElement('p');
+        description.className = 'content-item-description';
+        description.textContent = post.description;
+        var meta = document.createElement('p');
+        meta.className = 'content-item-meta';
+        meta.textContent = post.date + (post.tags.length ? ' #' + post.tags.join(', #') : '');
+        link.append(title, description, meta);
+        container.append(link);
+        return container;
+    }
+
+    function search() {
+        var query = words(input.value);
+        var typing = !/\s$/.test(input.value);
+        results.replaceChildren();
+
+        var url = new URL(location.href);
+        if (input.value) {
+            url.searchParams.set('q', input.value);
+        } else {
+            url.searchParams.delete('q');
+        }
+        history.replaceState(null, '', url);
+
+        if (!query.length) {
+            status.textContent = '';
+            return;
+        }
+
+        // Every query word has to match.
+        var found = posts.map(function (post) {
+            var scores = query.map(function (word, i) {
+                return wordScore(post, word, typing && i === query.length - 1);
+            });
+            var score = scores.every(function (s) { return s > 0; }) ? scores.reduce(function (a, b) { return a + b; }) : 0;
+            return { post: post, score: score };
+        }).filter(function (result) {
+            return result.score > 0;
+        }).sort(function (a, b) {
+            return b.score - a.score;
+        });
+
+        status.textContent = found.length === 1 ? '1 post found.' : found.length + ' posts found.';
+        found.forEach(function (result) {
+            results.append(item(result.post));
+        });
+    }
+
+    fetch(results.dataset.index)
+        .then(function (response) { return response.json(); })
+        .then(function (index) {
+            posts = index.posts;
+            input.value = new URL(location.href).searchParams.get('q') || input.value;
+            input.addEventListener('input', search);
+            search();
+        })
+        .catch(function () {
+            status.textContent = 'The search index could not be loaded.';
+        });
+})();